@@ -0,0 +1,509 @@
+/// This module contains the evaluation of arithmetic expressions
+/// used by `$((...))`, `((...))` and `let`, with the operators
+/// and precedence of C on 64-bit integers
+use crate::error::ShellError;
+use crate::shell::Shell;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Operators ordered so that the longest match is tried first
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+const ASSIGNMENTS: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+/// How deeply variables holding expressions may refer to other such variables
+const MAX_DEPTH: usize = 1024;
+
+/// Evaluates an expression whose parameters and commands were already expanded
+pub fn eval(expr: &str, shell: &mut Shell) -> Result<i64, ShellError> {
+    eval_nested(expr, shell, 0)
+}
+
+fn eval_nested(expr: &str, shell: &mut Shell, depth: usize) -> Result<i64, ShellError> {
+    let tokens = tokenize(expr)?;
+    let mut eval = Eval {
+        expr,
+        tokens,
+        pos: 0,
+        skip: 0,
+        depth,
+        shell,
+    };
+    if eval.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = eval.comma()?;
+    match eval.tokens.get(eval.pos) {
+        None => Ok(value),
+        Some(_) => Err(eval.error("syntax error in expression")),
+    }
+}
+
+/// Splits an expression into tokens, remembering where each one starts
+fn tokenize(expr: &str) -> Result<Vec<(Token, usize)>, ShellError> {
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < expr.len() {
+        let rest = &expr[i..];
+        let c = rest.chars().next().unwrap_or_default();
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '#' | '@')))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let token = match c.is_ascii_digit() {
+                true => Token::Num(number(word).ok_or_else(|| {
+                    let msg = "value too great for base";
+                    ShellError::Arith(
+                        expr.to_string(),
+                        format!("{msg} (error token is \"{word}\")"),
+                    )
+                })?),
+                // a name ends before any `#` or `@`
+                false => {
+                    let len = word.find(['#', '@']).unwrap_or(word.len());
+                    tokens.push((Token::Name(word[..len].to_string()), i));
+                    i += len;
+                    continue;
+                }
+            };
+            tokens.push((token, i));
+            i += len;
+            continue;
+        }
+
+        match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => {
+                tokens.push((Token::Op(op), i));
+                i += op.len();
+            }
+            None => {
+                let msg = format!(
+                    "syntax error: invalid arithmetic operator (error token is \"{rest}\")"
+                );
+                return Err(ShellError::Arith(expr.to_string(), msg));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses an integer constant: decimal, octal with a leading `0`,
+/// hexadecimal with `0x`, or in any base from 2 to 64 as `base#digits`
+fn number(s: &str) -> Option<i64> {
+    let (base, digits) = if let Some((base, digits)) = s.split_once('#') {
+        (
+            base.parse::<u32>().ok().filter(|b| (2..=64).contains(b))?,
+            digits,
+        )
+    } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        (16, hex)
+    } else if s.len() > 1 && s.starts_with('0') {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut n: i64 = 0;
+    for c in digits.chars() {
+        let d = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            // uppercase letters only differ from lowercase ones above base 36
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        if d >= base {
+            return None;
+        }
+        n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+    }
+    Some(n)
+}
+
+struct Eval<'a> {
+    expr: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Greater than zero while evaluating the side of `&&`, `||` or `?:`
+    /// that is not taken, where nothing is assigned and nothing fails
+    skip: usize,
+    depth: usize,
+    shell: &'a mut Shell,
+}
+
+impl Eval<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    /// Consumes the next token if it is one of the given operators
+    fn accept(&mut self, ops: &[&str]) -> Option<&'static str> {
+        let op = self.peek_op().filter(|op| ops.contains(op))?;
+        self.pos += 1;
+        Some(op)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ShellError> {
+        match self.accept(&[op]) {
+            Some(_) => Ok(()),
+            None => Err(self.error(&format!("syntax error: `{op}' expected"))),
+        }
+    }
+
+    /// An error pointing at the current token
+    fn error(&self, msg: &str) -> ShellError {
+        self.error_at(self.pos, msg)
+    }
+
+    /// An error pointing at the token at `pos`, or at the last one past the end
+    fn error_at(&self, pos: usize, msg: &str) -> ShellError {
+        let token = match self.tokens.get(pos).or(self.tokens.last()) {
+            Some((_, start)) => &self.expr[*start..],
+            None => "",
+        };
+        let msg = format!("{msg} (error token is \"{}\")", token.trim());
+        ShellError::Arith(self.expr.trim().to_string(), msg)
+    }
+
+    fn comma(&mut self) -> Result<i64, ShellError> {
+        let mut value = self.assignment()?;
+        while self.accept(&[","]).is_some() {
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, ShellError> {
+        let op = match (self.peek(), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Name(name)), Some((Token::Op(op), _))) if ASSIGNMENTS.contains(op) => {
+                Some((name.clone(), *op))
+            }
+            _ => None,
+        };
+        let Some((name, op)) = op else {
+            return self.ternary();
+        };
+        self.pos += 2;
+
+        let start = self.pos;
+        let rhs = self.assignment()?;
+        let value = match op {
+            "=" => rhs,
+            op => {
+                let lhs = self.var(&name)?;
+                self.binary(&op[..op.len() - 1], lhs, rhs, start)?
+            }
+        };
+        self.assign(&name, value)
+    }
+
+    fn ternary(&mut self) -> Result<i64, ShellError> {
+        let cond = self.binary_level(0)?;
+        if self.accept(&["?"]).is_none() {
+            return Ok(cond);
+        }
+
+        self.skip += (cond == 0) as usize;
+        let then = self.comma();
+        self.skip -= (cond == 0) as usize;
+        let then = then?;
+        self.expect(":")?;
+        self.skip += (cond != 0) as usize;
+        let otherwise = self.ternary();
+        self.skip -= (cond != 0) as usize;
+        let otherwise = otherwise?;
+
+        Ok(if cond != 0 { then } else { otherwise })
+    }
+
+    /// Parses the binary operators from the loosest level, `||`, to the tightest, `*`
+    fn binary_level(&mut self, level: usize) -> Result<i64, ShellError> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.power();
+        };
+
+        let mut lhs = self.binary_level(level + 1)?;
+        while let Some(op) = self.accept(ops) {
+            // the right side of `&&` and `||` is only evaluated when it matters
+            let short = match op {
+                "&&" => lhs == 0,
+                "||" => lhs != 0,
+                _ => false,
+            };
+            self.skip += short as usize;
+            let start = self.pos;
+            let rhs = self.binary_level(level + 1);
+            self.skip -= short as usize;
+            lhs = self.binary(op, lhs, rhs?, start)?;
+        }
+        Ok(lhs)
+    }
+
+    fn power(&mut self) -> Result<i64, ShellError> {
+        let base = self.unary()?;
+        if self.accept(&["**"]).is_none() {
+            return Ok(base);
+        }
+        let exp = self.power()?;
+        self.binary("**", base, exp, self.pos)
+    }
+
+    fn unary(&mut self) -> Result<i64, ShellError> {
+        if let Some(op) = self.accept(&["++", "--"]) {
+            let name = match self.peek() {
+                Some(Token::Name(name)) => name.clone(),
+                _ => return Err(self.error("syntax error: operand expected")),
+            };
+            self.pos += 1;
+            let value = self.var(&name)?;
+            let value = if op == "++" {
+                value.wrapping_add(1)
+            } else {
+                value.wrapping_sub(1)
+            };
+            return self.assign(&name, value);
+        }
+
+        match self.accept(&["-", "+", "!", "~"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("+") => self.unary(),
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some(_) => Ok(!self.unary()?),
+            None => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, ShellError> {
+        let name = match self.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+
+        let value = self.var(&name)?;
+        match self.accept(&["++", "--"]) {
+            Some("++") => self.assign(&name, value.wrapping_add(1))?,
+            Some(_) => self.assign(&name, value.wrapping_sub(1))?,
+            None => return Ok(value),
+        };
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<i64, ShellError> {
+        match self.peek().cloned() {
+            Some(Token::Num(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(self.error("syntax error: operand expected")),
+        }
+    }
+
+    /// Applies a binary operator, with errors pointing at the token at `at`
+    fn binary(&self, op: &str, lhs: i64, rhs: i64, at: usize) -> Result<i64, ShellError> {
+        let value = match op {
+            "||" => (lhs != 0 || rhs != 0) as i64,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "&" => lhs & rhs,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "<" => (lhs < rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => match self.skip {
+                0 => return Err(self.error_at(at, "division by 0")),
+                _ => 0,
+            },
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "**" if rhs < 0 => match self.skip {
+                0 => return Err(self.error_at(at, "exponent less than 0")),
+                _ => 0,
+            },
+            "**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+            _ => return Err(self.error_at(at, "syntax error in expression")),
+        };
+        Ok(value)
+    }
+
+    /// The value of a variable, which may itself hold an expression
+    fn var(&mut self, name: &str) -> Result<i64, ShellError> {
+        let value = match self.shell.vars.get(name) {
+            Some(value) if !value.trim().is_empty() => value.to_string(),
+            _ => return Ok(0),
+        };
+        if let Some(n) = number(value.trim()) {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("expression recursion level exceeded"));
+        }
+        eval_nested(&value, self.shell, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64, ShellError> {
+        if self.skip == 0 {
+            self.shell.vars.set(name, value.to_string())?;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(expr: &str) -> i64 {
+        eval(expr, &mut Shell::new()).unwrap()
+    }
+
+    /// The message of the error that evaluating `expr` fails with
+    fn error(expr: &str) -> String {
+        match eval(expr, &mut Shell::new()) {
+            Err(ShellError::Arith(_, msg)) => msg,
+            r => panic!("{expr}: expected an arithmetic error, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(calc("1 + 2 * 3"), 7);
+        assert_eq!(calc("(1 + 2) * 3"), 9);
+        assert_eq!(calc("2 ** 3 ** 2"), 512);
+        assert_eq!(calc("-2 ** 2"), 4);
+        assert_eq!(calc("1 << 2 + 1"), 8);
+        assert_eq!(calc("1 < 2 == 1"), 1);
+        assert_eq!(calc("1, 2, 3"), 3);
+    }
+
+    #[test]
+    fn division_truncates() {
+        assert_eq!(calc("-7 / 2"), -3);
+        assert_eq!(calc("7 % -3"), 1);
+        assert!(error("1 / 0").starts_with("division by 0"));
+        assert!(error("1 % 0").starts_with("division by 0"));
+    }
+
+    #[test]
+    fn overflow_wraps() {
+        assert_eq!(calc("2 ** 63"), i64::MIN);
+        assert_eq!(calc("9223372036854775807 + 1"), i64::MIN);
+        assert!(error("2 ** -1").starts_with("exponent less than 0"));
+    }
+
+    #[test]
+    fn bases() {
+        assert_eq!(calc("16#ff"), 255);
+        assert_eq!(calc("0x1F"), 31);
+        assert_eq!(calc("010"), 8);
+        assert_eq!(calc("2#101"), 5);
+        assert_eq!(calc("64#@"), 62);
+        assert_eq!(calc("64#_"), 63);
+        assert!(error("2#2").starts_with("value too great for base"));
+    }
+
+    #[test]
+    fn skipped_operands_are_not_evaluated() {
+        assert_eq!(calc("0 && 1 / 0"), 0);
+        assert_eq!(calc("1 || 1 / 0"), 1);
+        assert_eq!(calc("1 ? 2 : 1 / 0"), 2);
+        assert_eq!(calc("0 ? 1 / 0 : 3"), 3);
+    }
+
+    #[test]
+    fn variables() {
+        let mut shell = Shell::new();
+        shell.vars.set("x", "2 + 3".to_string()).unwrap();
+        assert_eq!(eval("x * 2", &mut shell).unwrap(), 10);
+        assert_eq!(eval("y = x++", &mut shell).unwrap(), 5);
+        assert_eq!(shell.vars.get("x"), Some("6"));
+        assert_eq!(shell.vars.get("y"), Some("5"));
+        assert_eq!(eval("y += 2, y", &mut shell).unwrap(), 7);
+        assert_eq!(eval("unset_name + 1", &mut shell).unwrap(), 1);
+    }
+
+    #[test]
+    fn recursion_is_limited() {
+        // on a stack as large as the shell's own, as the limit is set for it
+        let run = || {
+            let mut shell = Shell::new();
+            shell.vars.set("x", "x".to_string()).unwrap();
+            match eval("x", &mut shell) {
+                Err(ShellError::Arith(_, msg)) => {
+                    assert!(msg.starts_with("expression recursion level exceeded"))
+                }
+                r => panic!("expected a recursion error, got {r:?}"),
+            }
+        };
+        std::thread::Builder::new()
+            .stack_size(crate::STACK_SIZE)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(error("1 +").starts_with("syntax error"));
+        assert!(error("(1").starts_with("syntax error: `)' expected"));
+        assert!(error("1 = 2").starts_with("syntax error"));
+    }
+}
@@ -0,0 +1,617 @@
+//! This module contains the syntax tree produced by the parser
+//! and consumed by the shell when executing a line
+use crate::vars::is_name;
+use std::fmt::{Display, Formatter, Result};
+use std::rc::Rc;
+
+/// The words that are only recognized as commands when they are unquoted
+pub const KEYWORDS: &[&str] = &[
+    "!", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then",
+    "until", "while", "{", "}",
+];
+
+/// A sequence of and-or lists separated by `;` or newlines
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+/// Pipelines joined with `&&` and `||`, evaluated left to right
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Whether the list ends with `&`, running it as a job without waiting for it
+    pub background: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`, run the next pipeline if the previous one succeeded
+    And,
+    /// `||`, run the next pipeline if the previous one failed
+    Or,
+}
+
+/// Commands connected with `|`
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    /// Whether the pipeline starts with `!`, inverting its status
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// A compound command along with the redirections following it
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// `name() compound-command` or `function name compound-command`
+    Function(Rc<FunctionDef>),
+}
+
+/// A function definition, shared between the syntax tree and the shell's function table
+#[derive(Debug)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: CompoundCommand,
+    /// Redirections applied each time the function is called
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand {
+    /// `{ list; }`
+    Group(List),
+    /// `( list )`, run in a forked copy of the shell
+    Subshell(List),
+    /// `((expression))`
+    Arith(Word),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        /// Each condition along with the commands it guards
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is true
+    While {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for name [in word...]; do list; done`, where no words means `"$@"`
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `for ((init; condition; step)); do list; done`
+    ArithFor {
+        init: Word,
+        condition: Word,
+        step: Word,
+        body: List,
+    },
+    /// `case word in [(]pattern[|pattern]...) list;; ... esac`
+    Case { word: Word, items: Vec<CaseItem> },
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseTerminator {
+    /// `;;`, stop after this item
+    Break,
+    /// `;&`, run the next item's commands without testing its patterns
+    FallThrough,
+    /// `;;&`, go on testing the patterns of the following items
+    Continue,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCommand {
+    /// `NAME=value` words in front of the command name
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    /// The file descriptor given in front of the operator, e.g. the `2` in `2>`
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    /// The file name, or the body for here-documents and here-strings
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `>` and `>|`
+    Write,
+    /// `>>`
+    Append,
+    /// `<`
+    Read,
+    /// `<>`
+    ReadWrite,
+    /// `>&`, duplicating or closing an output descriptor, or `>&file` like `&>`
+    DupOut,
+    /// `<&`, duplicating or closing an input descriptor
+    DupIn,
+    /// `&>`, stdout and stderr to the same file
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+    /// `<<` and `<<-`, the target holds the body of the document
+    HereDoc,
+    /// `<<<`
+    HereString,
+}
+
+impl RedirectOp {
+    /// The file descriptor the redirection applies to when none is given
+    pub fn default_fd(&self) -> u32 {
+        match self {
+            RedirectOp::Write
+            | RedirectOp::Append
+            | RedirectOp::DupOut
+            | RedirectOp::WriteAll
+            | RedirectOp::AppendAll => 1,
+            RedirectOp::Read
+            | RedirectOp::ReadWrite
+            | RedirectOp::DupIn
+            | RedirectOp::HereDoc
+            | RedirectOp::HereString => 0,
+        }
+    }
+}
+
+/// A single shell word, keeping track of how each piece of it was quoted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text
+    Literal(String),
+    /// Text inside single quotes or escaped with a backslash
+    Quoted(String),
+    /// Text inside double quotes
+    DoubleQuoted(Vec<WordPart>),
+    /// A parameter expansion like `$NAME`, `${NAME:-word}` or `$?`
+    Param(Param),
+    /// A command substitution, `$(...)` or `` `...` ``, holding the source of the commands
+    Command(String),
+    /// An arithmetic expansion `$((...))`
+    Arith(Word),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub op: ParamOp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    /// `${NAME}`
+    Value,
+    /// `${#NAME}`
+    Length,
+    /// `${NAME:-word}`, or `${NAME-word}` when `colon` is false
+    Default { colon: bool, word: Word },
+    /// `${NAME:=word}`
+    Assign { colon: bool, word: Word },
+    /// `${NAME:?word}`
+    Error { colon: bool, word: Word },
+    /// `${NAME:+word}`
+    Alternative { colon: bool, word: Word },
+    /// `${NAME#pattern}`, or `${NAME##pattern}` when `longest` is true
+    RemovePrefix { longest: bool, pattern: Word },
+    /// `${NAME%pattern}` and `${NAME%%pattern}`
+    RemoveSuffix { longest: bool, pattern: Word },
+    /// `${NAME/pattern/replacement}` and its `//`, `/#` and `/%` forms
+    Replace {
+        mode: ReplaceMode,
+        pattern: Word,
+        replacement: Word,
+    },
+    /// `${NAME:offset}` and `${NAME:offset:length}`
+    Substring { offset: Word, length: Option<Word> },
+    /// `${NAME^}`, `${NAME^^}`, `${NAME,}` and `${NAME,,}`
+    Case { upper: bool, all: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+impl Word {
+    /// Returns the reserved word this word spells, if it is unquoted
+    pub fn keyword(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(s)] if KEYWORDS.contains(&s.as_str()) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the word with all quoting removed
+    pub fn unquoted(&self) -> String {
+        let mut res = String::new();
+        for part in &self.parts {
+            part.push_unquoted(&mut res);
+        }
+        res
+    }
+}
+
+impl Word {
+    /// Splits a word of the form `NAME=value` with an unquoted name into an assignment
+    pub fn as_assignment(&self) -> Option<Assignment> {
+        let (first, rest) = self.parts.split_first()?;
+        let (name, value) = match first {
+            WordPart::Literal(s) => s.split_once('=')?,
+            _ => return None,
+        };
+        if !is_name(name) {
+            return None;
+        }
+
+        let mut parts = vec![];
+        if !value.is_empty() {
+            parts.push(WordPart::Literal(value.to_string()));
+        }
+        parts.extend(rest.iter().cloned());
+        Some(Assignment {
+            name: name.to_string(),
+            value: Word { parts },
+        })
+    }
+}
+
+impl WordPart {
+    fn push_unquoted(&self, res: &mut String) {
+        match self {
+            WordPart::Literal(s) | WordPart::Quoted(s) => res.push_str(s),
+            WordPart::DoubleQuoted(parts) => {
+                for part in parts {
+                    part.push_unquoted(res);
+                }
+            }
+            WordPart::Param(param) => {
+                res.push_str("${");
+                res.push_str(&param.name);
+                res.push('}');
+            }
+            WordPart::Command(src) => {
+                res.push_str("$(");
+                res.push_str(src);
+                res.push(')');
+            }
+            WordPart::Arith(word) => {
+                res.push_str("$((");
+                res.push_str(&word.unquoted());
+                res.push_str("))");
+            }
+        }
+    }
+}
+
+// The commands are written back out in the form bash uses for `jobs`,
+// which is also valid input for the parser. Here-documents only show
+// their operator, since the delimiter isn't kept.
+
+impl Display for List {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{and_or}")?;
+            match and_or.background {
+                true => write!(f, " &")?,
+                false if i + 1 < self.items.len() => write!(f, ";")?,
+                false => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for AndOr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let op = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {op} {pipeline}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{command}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Command::Simple(simple) => write!(f, "{simple}"),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{compound}")?;
+                redirects.iter().try_for_each(|r| write!(f, " {r}"))
+            }
+            Command::Function(def) => {
+                write!(f, "{} () {}", def.name, def.body)?;
+                def.redirects.iter().try_for_each(|r| write!(f, " {r}"))
+            }
+        }
+    }
+}
+
+impl Display for CompoundCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            CompoundCommand::Group(list) => write!(f, "{{ {list}{} }}", terminator(list)),
+            CompoundCommand::Subshell(list) => write!(f, "( {list} )"),
+            CompoundCommand::Arith(word) => write!(f, "(( {word} ))"),
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let kw = if i == 0 { "if" } else { "elif" };
+                    write!(f, "{kw} {condition}{}", terminator(condition))?;
+                    write!(f, " then {body}{} ", terminator(body))?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {otherwise}{} ", terminator(otherwise))?;
+                }
+                write!(f, "fi")
+            }
+            CompoundCommand::While {
+                until,
+                condition,
+                body,
+            } => {
+                let kw = if *until { "until" } else { "while" };
+                write!(f, "{kw} {condition}{}", terminator(condition))?;
+                write!(f, " do {body}{} done", terminator(body))
+            }
+            CompoundCommand::For { name, words, body } => {
+                write!(f, "for {name}")?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    words.iter().try_for_each(|w| write!(f, " {w}"))?;
+                }
+                write!(f, "; do {body}{} done", terminator(body))
+            }
+            CompoundCommand::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                write!(f, "for (({init}; {condition}; {step})); ")?;
+                write!(f, "do {body}{} done", terminator(body))
+            }
+            CompoundCommand::Case { word, items } => {
+                write!(f, "case {word} in")?;
+                for item in items {
+                    write!(f, " ")?;
+                    for (i, pattern) in item.patterns.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, " | ")?;
+                        }
+                        write!(f, "{pattern}")?;
+                    }
+                    write!(f, ") ")?;
+                    if !item.body.items.is_empty() {
+                        write!(f, "{}", item.body)?;
+                    }
+                    let op = match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    };
+                    write!(f, "{op}")?;
+                }
+                write!(f, " esac")
+            }
+        }
+    }
+}
+
+/// The `;` needed after a list before a reserved word, which a trailing `&` already ends
+fn terminator(list: &List) -> &'static str {
+    match list.items.last() {
+        Some(and_or) if and_or.background => "",
+        _ => ";",
+    }
+}
+
+impl Display for SimpleCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let mut sep = "";
+        for assignment in &self.assignments {
+            write!(f, "{sep}{}={}", assignment.name, assignment.value)?;
+            sep = " ";
+        }
+        for word in &self.words {
+            write!(f, "{sep}{word}")?;
+            sep = " ";
+        }
+        for redirect in &self.redirects {
+            write!(f, "{sep}{redirect}")?;
+            sep = " ";
+        }
+        Ok(())
+    }
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{fd}")?;
+        }
+        match self.op {
+            RedirectOp::Write => write!(f, "> {}", self.target),
+            RedirectOp::Append => write!(f, ">> {}", self.target),
+            RedirectOp::Read => write!(f, "< {}", self.target),
+            RedirectOp::ReadWrite => write!(f, "<> {}", self.target),
+            RedirectOp::DupOut => write!(f, ">&{}", self.target),
+            RedirectOp::DupIn => write!(f, "<&{}", self.target),
+            RedirectOp::WriteAll => write!(f, "&> {}", self.target),
+            RedirectOp::AppendAll => write!(f, "&>> {}", self.target),
+            RedirectOp::HereDoc => write!(f, "<<"),
+            RedirectOp::HereString => write!(f, "<<< {}", self.target),
+        }
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_parts(f, &self.parts, false)
+    }
+}
+
+/// Writes the parts of a word, escaping literal text for double quotes when
+/// `quoted`. Parameters keep their braces only where the text after them
+/// would otherwise be read as part of the name.
+fn write_parts(f: &mut Formatter<'_>, parts: &[WordPart], quoted: bool) -> Result {
+    let mut single = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        // neighbouring quoted pieces like `'it'\''s'` go into one pair of quotes
+        if let (WordPart::Quoted(s), false) = (part, quoted) {
+            single.push_str(s);
+            if !matches!(parts.get(i + 1), Some(WordPart::Quoted(_))) {
+                write!(f, "'{}'", single.replace('\'', "'\\''"))?;
+                single.clear();
+            }
+            continue;
+        }
+        match part {
+            WordPart::Literal(s) | WordPart::Quoted(s) if quoted => {
+                for c in s.chars() {
+                    if matches!(c, '"' | '$' | '`' | '\\') {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{c}")?;
+                }
+            }
+            WordPart::Param(param) if param.op == ParamOp::Value => {
+                let next = match parts.get(i + 1) {
+                    Some(WordPart::Literal(s) | WordPart::Quoted(s)) => s.chars().next(),
+                    _ => None,
+                };
+                let special = param.name.len() == 1 && !is_name(&param.name);
+                let joins = next.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+                match special || (is_name(&param.name) && !joins) {
+                    true => write!(f, "${}", param.name)?,
+                    false => write!(f, "{param}")?,
+                }
+            }
+            part => write!(f, "{part}")?,
+        }
+    }
+    Ok(())
+}
+
+impl Display for WordPart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            WordPart::Literal(s) => write!(f, "{s}"),
+            WordPart::Quoted(s) => write!(f, "'{}'", s.replace('\'', "'\\''")),
+            WordPart::DoubleQuoted(parts) => {
+                write!(f, "\"")?;
+                write_parts(f, parts, true)?;
+                write!(f, "\"")
+            }
+            WordPart::Param(param) => write!(f, "{param}"),
+            WordPart::Command(src) => write!(f, "$({src})"),
+            WordPart::Arith(word) => write!(f, "$(({word}))"),
+        }
+    }
+}
+
+impl Display for Param {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let name = &self.name;
+        let colon = |colon: &bool| if *colon { ":" } else { "" };
+        match &self.op {
+            ParamOp::Value => write!(f, "${{{name}}}"),
+            ParamOp::Length => write!(f, "${{#{name}}}"),
+            ParamOp::Default { colon: c, word } => write!(f, "${{{name}{}-{word}}}", colon(c)),
+            ParamOp::Assign { colon: c, word } => write!(f, "${{{name}{}={word}}}", colon(c)),
+            ParamOp::Error { colon: c, word } => write!(f, "${{{name}{}?{word}}}", colon(c)),
+            ParamOp::Alternative { colon: c, word } => {
+                write!(f, "${{{name}{}+{word}}}", colon(c))
+            }
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let op = if *longest { "##" } else { "#" };
+                write!(f, "${{{name}{op}{pattern}}}")
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let op = if *longest { "%%" } else { "%" };
+                write!(f, "${{{name}{op}{pattern}}}")
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let op = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                write!(f, "${{{name}{op}{pattern}/{replacement}}}")
+            }
+            ParamOp::Substring { offset, length } => {
+                write!(f, "${{{name}:{offset}")?;
+                if let Some(length) = length {
+                    write!(f, ":{length}")?;
+                }
+                write!(f, "}}")
+            }
+            ParamOp::Case { upper, all } => {
+                let op = if *upper { "^" } else { "," };
+                let op = if *all { op.repeat(2) } else { op.to_string() };
+                write!(f, "${{{name}{op}}}")
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::builtins::BUILTINS;
 use crate::trie::Trie;
 use is_executable::is_executable;
 use rustyline::completion::{Completer, Pair};
@@ -12,15 +13,15 @@ use std::path::PathBuf;
 #[derive(Clone)]
 pub struct ShellAutocomplete {
     pub suggestions: Trie,
+    /// The names of the shell's aliases, which change while it runs
+    pub aliases: Trie,
 }
 
 impl ShellAutocomplete {
     pub fn new(path: &Vec<PathBuf>) -> Self {
         let mut res = Trie::new();
 
-        vec!["exit", "echo", "type", "pwd"]
-            .into_iter()
-            .for_each(|x| res.add(x.chars()));
+        BUILTINS.iter().for_each(|x| res.add(x.chars()));
 
         for p in path {
             let entries = match p.read_dir() {
@@ -42,7 +43,15 @@ impl ShellAutocomplete {
                 res.add(name.chars());
             }
         }
-        ShellAutocomplete { suggestions: res }
+        ShellAutocomplete {
+            suggestions: res,
+            aliases: Trie::new(),
+        }
+    }
+
+    pub fn set_aliases<'a>(&mut self, names: impl Iterator<Item = &'a String>) {
+        self.aliases = Trie::new();
+        names.for_each(|x| self.aliases.add(x.chars()));
     }
 }
 
@@ -56,7 +65,9 @@ impl Completer for ShellAutocomplete {
         _: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
         let mut fs = self.suggestions.fuzzy(line.chars());
+        fs.append(&mut self.aliases.fuzzy(line.chars()));
         fs.sort();
+        fs.dedup();
 
         if fs.is_empty() {
             print!("\x07");
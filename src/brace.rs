@@ -0,0 +1,223 @@
+/// This module contains brace expansion, which turns a word like
+/// `file{,.bak}` or `{1..3}` into several words before any other
+/// expansion takes place
+use crate::ast::{Word, WordPart};
+
+/// A piece of a word, either an unquoted character,
+/// which may be part of a brace expression, or anything else
+#[derive(Debug, Clone)]
+enum Unit {
+    Char(char),
+    Part(WordPart),
+}
+
+pub fn expand(word: &Word) -> Vec<Word> {
+    let mut units = vec![];
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(s) => units.extend(s.chars().map(Unit::Char)),
+            part => units.push(Unit::Part(part.clone())),
+        }
+    }
+    if !units.iter().any(|u| is_char(u, '{')) {
+        return vec![word.clone()];
+    }
+    expand_units(units).into_iter().map(to_word).collect()
+}
+
+fn expand_units(units: Vec<Unit>) -> Vec<Vec<Unit>> {
+    for open in 0..units.len() {
+        if !is_char(&units[open], '{') {
+            continue;
+        }
+        let Some((close, commas)) = find_close(&units, open) else {
+            continue;
+        };
+
+        let alternatives = if !commas.is_empty() {
+            let mut bounds = vec![open];
+            bounds.extend(commas);
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .map(|w| units[w[0] + 1..w[1]].to_vec())
+                .collect::<Vec<_>>()
+        } else if let Some(items) = sequence(&units[open + 1..close]) {
+            items
+                .into_iter()
+                .map(|s| s.chars().map(Unit::Char).collect())
+                .collect()
+        } else {
+            // something like `{a}` stays as it is, but may contain other braces
+            continue;
+        };
+
+        let mut res = vec![];
+        for alternative in alternatives {
+            let mut word = units[..open].to_vec();
+            word.extend(alternative);
+            word.extend_from_slice(&units[close + 1..]);
+            res.extend(expand_units(word));
+        }
+        return res;
+    }
+    vec![units]
+}
+
+/// Finds the brace matching the one at `open` along with
+/// the positions of the commas directly inside of it
+fn find_close(units: &[Unit], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    for (i, unit) in units.iter().enumerate().skip(open + 1) {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') if depth == 0 => return Some((i, commas)),
+            Unit::Char('}') => depth -= 1,
+            Unit::Char(',') if depth == 0 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Expands the inside of a sequence expression like `{1..10}`,
+/// `{01..10..2}` or `{a..z}`
+fn sequence(units: &[Unit]) -> Option<Vec<String>> {
+    let text = units
+        .iter()
+        .map(|u| match u {
+            Unit::Char(c) => Some(*c),
+            Unit::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+
+    let bounds = text.split("..").collect::<Vec<_>>();
+    let (start, end, step) = match bounds.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (
+            *start,
+            *end,
+            step.parse::<i64>().ok()?.checked_abs()?.max(1),
+        ),
+        _ => return None,
+    };
+
+    if let (Ok(a), Ok(b)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        // a leading zero on either end pads every number to the same width
+        let padded = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = match padded(start) || padded(end) {
+            true => start.len().max(end.len()),
+            false => 0,
+        };
+        return Some(range(a, b, step)?.map(|n| format!("{n:0width$}")).collect());
+    }
+
+    let mut chars = (start.chars(), end.chars());
+    match (
+        chars.0.next(),
+        chars.0.next(),
+        chars.1.next(),
+        chars.1.next(),
+    ) {
+        (Some(a), None, Some(b), None) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+            let items = range(a as i64, b as i64, step)?
+                .filter_map(|n| char::from_u32(n as u32))
+                .map(String::from)
+                .collect();
+            Some(items)
+        }
+        _ => None,
+    }
+}
+
+/// The numbers from `a` to `b` in steps of `step`, counting down if `b` is smaller,
+/// or `None` if they are too far apart to count
+fn range(a: i64, b: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = a.checked_sub(b)?.checked_abs()? / step + 1;
+    let step = if a <= b { step } else { -step };
+    Some((0..count).map(move |i| a + i * step))
+}
+
+fn is_char(unit: &Unit, c: char) -> bool {
+    matches!(unit, Unit::Char(x) if *x == c)
+}
+
+fn to_word(units: Vec<Unit>) -> Word {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    for unit in units {
+        match unit {
+            Unit::Char(c) => literal.push(c),
+            Unit::Part(part) => {
+                if !literal.is_empty() {
+                    parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(part);
+            }
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(parts: Vec<WordPart>) -> Vec<String> {
+        expand(&Word { parts }).iter().map(Word::unquoted).collect()
+    }
+
+    fn literal(s: &str) -> Vec<String> {
+        words(vec![WordPart::Literal(s.to_string())])
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(literal("file{,.bak}"), ["file", "file.bak"]);
+        assert_eq!(literal("{a,b}{1..3}"), ["a1", "a2", "a3", "b1", "b2", "b3"]);
+        assert_eq!(literal("{a,{b,c}}d"), ["ad", "bd", "cd"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(literal("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(literal("{3..1}"), ["3", "2", "1"]);
+        // the sign of the step is ignored, the ends pick the direction
+        assert_eq!(literal("{1..10..-3}"), ["1", "4", "7", "10"]);
+        assert_eq!(literal("{10..1..3}"), ["10", "7", "4", "1"]);
+        assert_eq!(literal("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(literal("{01..03}"), ["01", "02", "03"]);
+    }
+
+    #[test]
+    fn not_expanded() {
+        assert_eq!(literal("{a}"), ["{a}"]);
+        assert_eq!(literal("{a,b"), ["{a,b"]);
+        assert_eq!(literal("{1..b}"), ["{1..b}"]);
+        assert_eq!(literal("{}"), ["{}"]);
+        // ends or steps too large to count with
+        assert_eq!(
+            literal("{-9223372036854775808..9223372036854775807}"),
+            ["{-9223372036854775808..9223372036854775807}"]
+        );
+        assert_eq!(
+            literal("{1..2..-9223372036854775808}"),
+            ["{1..2..-9223372036854775808}"]
+        );
+    }
+
+    #[test]
+    fn quoted_parts_are_kept() {
+        let parts = vec![
+            WordPart::Literal("{a,b}".to_string()),
+            WordPart::Quoted("{c,d}".to_string()),
+        ];
+        assert_eq!(words(parts), ["a{c,d}", "b{c,d}"]);
+    }
+}
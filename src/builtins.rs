@@ -1,84 +1,330 @@
 /// This module contains the builtin commands supported
 /// by our shell and the execution logic
+use crate::arith;
+use crate::ast::{FunctionDef, KEYWORDS};
 use crate::command::Command;
-use crate::shell::Shell;
+use crate::error::ShellError;
+use crate::jobs::JobState;
+use crate::shell::{Flow, Shell};
+use crate::shell_io::{Input, Output};
+use crate::signals;
+use crate::traps::Trap;
+use crate::vars::{is_name, quote, Var};
 use is_executable::is_executable;
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::{Child, Stdio};
+use std::io::{ErrorKind, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Stdio};
 
 pub enum ChildOrStatus {
     Child(Child),
+    /// A forked copy of the shell running a command of a pipeline
+    Forked(libc::pid_t),
     Status(i32),
 }
 
 const OK: ChildOrStatus = ChildOrStatus::Status(0);
 const NOT_OK: ChildOrStatus = ChildOrStatus::Status(1);
 
+pub const BUILTINS: &[&str] = &[
+    ".", "alias", "bg", "break", "cd", "continue", "disown", "echo", "eval", "exit", "export",
+    "fg", "history", "jobs", "kill", "let", "local", "pwd", "readonly", "return", "set", "shopt",
+    "source", "trap", "type", "unalias", "unset", "wait",
+];
+
+/// The options `shopt` can turn on and off
+pub const SHOPTS: &[&str] = &["dotglob", "failglob", "globstar", "nullglob"];
+
 impl ChildOrStatus {
+    /// The process running the command, if it isn't done yet
+    pub fn pid(&self) -> Option<libc::pid_t> {
+        match self {
+            ChildOrStatus::Child(c) => Some(c.id() as libc::pid_t),
+            ChildOrStatus::Forked(pid) => Some(*pid),
+            ChildOrStatus::Status(_) => None,
+        }
+    }
+
     pub fn wait(self) -> i32 {
         match self {
-            ChildOrStatus::Child(mut c) => {
-                c.wait().unwrap_or_default().code().unwrap_or_default()
+            ChildOrStatus::Child(mut c) => match c.wait() {
+                Ok(status) => exit_status(status),
+                Err(_) => 1,
+            },
+            ChildOrStatus::Forked(pid) => match Shell::wait_pid(pid) {
+                Ok(status) => exit_status(status),
+                Err(_) => 1,
             },
             ChildOrStatus::Status(s) => s,
         }
     }
 }
 
+/// Converts the status of a finished child into a shell exit status,
+/// reporting children killed by a signal as 128 plus the signal number
+pub fn exit_status(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    match status.signal() {
+        Some(signo) => {
+            report_signal(signo, status.core_dumped());
+            128 + signo
+        }
+        None => 1,
+    }
+}
+
+/// Reports a command killed by a signal, except for the interrupts
+/// and broken pipes that are usually intended
+pub fn report_signal(signo: i32, core_dumped: bool) {
+    if signo != libc::SIGINT && signo != libc::SIGPIPE {
+        let core = if core_dumped { " (core dumped)" } else { "" };
+        eprintln!("{}{}", signals::describe(signo), core);
+    }
+}
+
 impl Command {
-    pub fn execute(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+    pub fn execute(&mut self, shell: &mut Shell) -> Result<ChildOrStatus, ShellError> {
+        if let Some(def) = shell.functions.get(&self.name).cloned() {
+            return self.call(shell, &def);
+        }
+
         match self.name.as_str() {
+            _ if self.args.is_empty() => Ok(OK),
+            "source" | "." => self.source(shell),
+            "eval" => self.eval(shell),
+            name if BUILTINS.contains(&name) => self.builtin(shell),
+            _ => self.spawn(shell),
+        }
+    }
+
+    fn builtin(&mut self, shell: &mut Shell) -> Result<ChildOrStatus, ShellError> {
+        let r = match self.name.as_str() {
             "exit" => {
-                let _ = shell.write_history(&shell.hist_file);
-                self.exit()
+                if shell.interactive && !shell.subshell {
+                    let _ = shell.write_history(shell.hist_file());
+                }
+                self.exit(shell)
             }
             "echo" => self.echo(),
-            "type" => self.cmd_type(&shell.path),
+            "type" => self.cmd_type(shell),
             "pwd" => self.pwd(&shell.pwd),
             "cd" => self.cd(shell),
             "history" => self.history(shell),
-            _ => {
-                if let Some(exe) = self.find_executable(&self.name, &shell.path) {
-                    let mut cmd = std::process::Command::new(exe.file_name().unwrap());
-                    cmd.stdin(Stdio::from(self.stdin.take()));
-                    cmd.stdout(Stdio::from(self.stdout.take()));
-                    cmd.stderr(Stdio::from(self.stderr.take()));
-
-                    if self.args.len() > 1 {
-                        cmd.args(&self.args[1..]);
-                    }
-                    let child = cmd.spawn()?;
+            "export" => self.export(shell),
+            "readonly" => self.readonly(shell),
+            "unset" => self.unset(shell),
+            "set" => self.set(shell),
+            "let" => self.let_(shell),
+            "shopt" => self.shopt(shell),
+            "break" | "continue" => self.loop_control(shell),
+            "local" => self.local(shell),
+            "alias" => self.alias(shell),
+            "unalias" => self.unalias(shell),
+            "return" => self.return_(shell),
+            "jobs" => self.jobs(shell),
+            "fg" => self.fg(shell),
+            "bg" => self.bg(shell),
+            "wait" => self.wait(shell),
+            "disown" => self.disown(shell),
+            "trap" => self.trap(shell),
+            "kill" => self.kill(shell),
+            _ => return self.spawn(shell),
+        };
+        r.map_err(|e| ShellError::Builtin(self.name.clone(), e))
+    }
 
-                    Ok(ChildOrStatus::Child(child))
-                } else {
-                    let (_, _, mut stderr) = self.take_io();
-                    let _ = write!(stderr, "{}: command not found\n", self.name);
-                    Ok(NOT_OK)
-                }
+    /// Calls a shell function with the command's arguments and I/O
+    fn call(&mut self, shell: &mut Shell, def: &FunctionDef) -> Result<ChildOrStatus, ShellError> {
+        let args = self.args[1..].to_vec();
+        self.run_in_shell(shell, |shell| shell.call_function(def, args))
+    }
+
+    /// `source file [args]` and `. file [args]`, running the commands in a file
+    /// with the arguments as positional parameters while it runs
+    fn source(&mut self, shell: &mut Shell) -> Result<ChildOrStatus, ShellError> {
+        let Some(name) = self.args.get(1).cloned() else {
+            let (_, _, mut stderr) = self.take_io();
+            writeln!(stderr, "shell: {}: filename argument required", self.name)
+                .map_err(|e| ShellError::Builtin(self.name.clone(), e))?;
+            return Ok(ChildOrStatus::Status(2));
+        };
+
+        // a name without a slash is looked up in $PATH, then in the current directory
+        let path = match name.contains('/') {
+            true => None,
+            false => shell
+                .path()
+                .into_iter()
+                .map(|dir| dir.join(&name))
+                .find(|p| p.is_file()),
+        };
+        let text = std::fs::read(path.as_deref().unwrap_or(Path::new(&name)))
+            .map_err(|e| ShellError::Read(name.clone(), e))?;
+        let args = self
+            .args
+            .get(2..)
+            .filter(|args| !args.is_empty())
+            .map(<[_]>::to_vec);
+
+        self.run_in_shell(shell, |shell| {
+            let positional = args.map(|args| std::mem::replace(&mut shell.positional, args));
+            let r = shell.run_source(&name, &text);
+            if let Some(positional) = positional {
+                shell.positional = positional;
+            }
+            r
+        })
+    }
+
+    /// `eval args...`, running the arguments joined with spaces as commands
+    fn eval(&mut self, shell: &mut Shell) -> Result<ChildOrStatus, ShellError> {
+        let src = self.args[1..].join(" ");
+        self.run_in_shell(shell, |shell| {
+            Ok(shell.run_lines("eval", src.lines().map(String::from)))
+        })
+    }
+
+    /// Runs `f` inside the shell with the command's I/O in place of the shell's own
+    fn run_in_shell(
+        &mut self,
+        shell: &mut Shell,
+        f: impl FnOnce(&mut Shell) -> Result<i32, ShellError>,
+    ) -> Result<ChildOrStatus, ShellError> {
+        let saved = self.install()?;
+        let r = f(shell);
+        saved.restore();
+        Ok(ChildOrStatus::Status(r?))
+    }
+
+    fn spawn(&mut self, shell: &Shell) -> Result<ChildOrStatus, ShellError> {
+        let exe = if self.name.contains('/') {
+            PathBuf::from(&self.name)
+        } else {
+            self.find_executable(&self.name, &shell.path())
+                .ok_or_else(|| ShellError::CommandNotFound(self.name.clone()))?
+        };
+
+        let mut cmd = std::process::Command::new(exe);
+        cmd.arg0(&self.name);
+        cmd.env_clear();
+        cmd.envs(shell.vars.exported());
+        let closed = [
+            matches!(self.stdin, Input::Closed),
+            matches!(self.stdout, Output::Closed),
+            matches!(self.stderr, Output::Closed),
+        ];
+        cmd.stdin(Stdio::from(self.stdin.take()));
+        cmd.stdout(Stdio::from(self.stdout.take()));
+        cmd.stderr(Stdio::from(self.stderr.take()));
+        self.redirect_fds(&mut cmd, closed);
+
+        if self.args.len() > 1 {
+            cmd.args(&self.args[1..]);
+        }
+
+        if shell.job_control() {
+            cmd.process_group(shell.pgid.unwrap_or(0));
+        }
+        if shell.terminal.is_some() {
+            // SAFETY: only async-signal-safe libc calls are made in the child
+            unsafe {
+                cmd.pre_exec(|| {
+                    signals::default_job_signals();
+                    Ok(())
+                });
+            }
+        }
+        if self.exec {
+            // only returns if the program couldn't be run
+            let e = cmd.exec();
+            self.stderr = Output::None;
+            return Err(ShellError::NotExecutable(self.name.clone(), e));
+        }
+        match cmd.spawn() {
+            Ok(child) => Ok(ChildOrStatus::Child(child)),
+            Err(e) => {
+                // the handles were given to the failed child, so report on the shell's stderr
+                self.stderr = Output::None;
+                Err(ShellError::NotExecutable(self.name.clone(), e))
             }
         }
     }
 
-    fn cmd_type(&mut self, path: &Vec<PathBuf>) -> std::io::Result<ChildOrStatus> {
+    /// Applies closed standard descriptors and the redirections
+    /// of descriptors above 2 in the child before it execs
+    fn redirect_fds(&self, cmd: &mut std::process::Command, closed: [bool; 3]) {
+        if self.fds.is_empty() && !closed.contains(&true) {
+            return;
+        }
+
+        let mut fds = self
+            .fds
+            .iter()
+            .map(|(fd, f)| (*fd as i32, f.as_ref().map(|f| f.as_raw_fd())))
+            .collect::<Vec<_>>();
+        let floor = fds.iter().map(|(fd, _)| fd + 1).max().unwrap_or(3);
+
+        // SAFETY: only async-signal-safe libc calls are made in the child
+        unsafe {
+            cmd.pre_exec(move || {
+                for (fd, is_closed) in closed.iter().enumerate() {
+                    if *is_closed {
+                        libc::close(fd as i32);
+                    }
+                }
+                // move every source above all targets first so that
+                // placing one descriptor cannot clobber another source
+                for (_, source) in fds.iter_mut() {
+                    if let Some(s) = source {
+                        *s = libc::fcntl(*s, libc::F_DUPFD, floor);
+                    }
+                }
+                for (fd, source) in &fds {
+                    match source {
+                        Some(s) if libc::dup2(*s, *fd) < 0 => {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Some(_) => {}
+                        None => {
+                            libc::close(*fd);
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn cmd_type(&mut self, shell: &Shell) -> std::io::Result<ChildOrStatus> {
         let (_, mut stdout, mut stderr) = self.take_io();
 
         if self.args.len() < 2 {
-            write!(stderr, "Usage: type <command>\n")?;
+            writeln!(stderr, "Usage: type <command>")?;
             return Ok(NOT_OK);
         }
 
         let cmd = &self.args[1];
 
         match cmd.as_str() {
-            "exit" | "echo" | "type" | "pwd" | "history" => {
-                write!(stdout, "{} is a shell builtin\n", cmd)
+            _ if shell.aliases.contains_key(cmd) => {
+                writeln!(stdout, "{} is aliased to `{}'", cmd, shell.aliases[cmd])
+            }
+            _ if KEYWORDS.contains(&cmd.as_str()) => {
+                writeln!(stdout, "{} is a shell keyword", cmd)
+            }
+            _ if shell.functions.contains_key(cmd) => writeln!(stdout, "{} is a function", cmd),
+            _ if BUILTINS.contains(&cmd.as_str()) => {
+                writeln!(stdout, "{} is a shell builtin", cmd)
             }
             _ => {
-                if let Some(path_str) = self.find_executable(cmd, path) {
-                    write!(stdout, "{} is {}\n", cmd, path_str.to_str().unwrap_or(""))
+                if let Some(path_str) = self.find_executable(cmd, &shell.path()) {
+                    writeln!(stdout, "{} is {}", cmd, path_str.to_str().unwrap_or(""))
                 } else {
-                    write!(stderr, "{}: not found\n", cmd)
+                    writeln!(stderr, "{}: not found", cmd)
                 }
             }
         }?;
@@ -88,28 +334,836 @@ impl Command {
 
     fn cd(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
         let (_, _, mut stderr) = self.take_io();
-        let home = std::env::var("HOME").unwrap_or("~".into());
+        // a `~` argument has already been turned into the home directory
+        let dir = match self.args.get(1) {
+            Some(dir) => dir.clone(),
+            None => match shell.vars.get("HOME") {
+                Some(home) => home.to_string(),
+                None => {
+                    writeln!(stderr, "shell: cd: HOME not set")?;
+                    return Ok(NOT_OK);
+                }
+            },
+        };
+        let p = PathBuf::from(&dir);
+
+        if !p.exists() {
+            writeln!(stderr, "cd: {dir}: No such file or directory")?;
+            return Ok(NOT_OK);
+        }
+
+        let old = shell.pwd.display().to_string();
+        shell.pwd = p.canonicalize()?;
+        std::env::set_current_dir(p)?;
+
+        let pwd = shell.pwd.display().to_string();
+        for (name, value) in [("OLDPWD", old), ("PWD", pwd)] {
+            if let Err(e) = shell.vars.set(name, value) {
+                writeln!(stderr, "shell: cd: {e}")?;
+            }
+        }
+
+        Ok(OK)
+    }
+
+    fn export(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let mut exported = true;
+        let mut names = vec![];
+        for arg in &self.args[1..] {
+            match arg.as_str() {
+                "-n" => exported = false,
+                "-p" => {}
+                _ => names.push(arg),
+            }
+        }
+
+        if names.is_empty() {
+            for (name, var) in shell.vars.iter() {
+                if !var.exported {
+                    continue;
+                }
+                match &var.value {
+                    Some(value) => writeln!(stdout, "export {}={}", name, quote(value))?,
+                    None => writeln!(stdout, "export {}", name)?,
+                }
+            }
+            return Ok(OK);
+        }
 
+        let mut status = OK;
+        for arg in names {
+            let (name, value) = split_assignment(arg);
+            let r = self.declare(shell, name, value).map(|_| {
+                shell.vars.export(name, exported);
+            });
+            if let Err(e) = r {
+                writeln!(stderr, "shell: export: {e}")?;
+                status = NOT_OK;
+            }
+        }
+        Ok(status)
+    }
+
+    fn readonly(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let names = self.args[1..]
+            .iter()
+            .filter(|a| *a != "-p")
+            .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            for (name, var) in shell.vars.iter() {
+                if !var.readonly {
+                    continue;
+                }
+                match &var.value {
+                    Some(value) => writeln!(stdout, "readonly {}={}", name, quote(value))?,
+                    None => writeln!(stdout, "readonly {}", name)?,
+                }
+            }
+            return Ok(OK);
+        }
+
+        let mut status = OK;
+        for arg in names {
+            let (name, value) = split_assignment(arg);
+            let r = self.declare(shell, name, value).map(|_| {
+                shell.vars.set_readonly(name);
+            });
+            if let Err(e) = r {
+                writeln!(stderr, "shell: readonly: {e}")?;
+                status = NOT_OK;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Validates the name for `export` and `readonly` and assigns the value if one was given
+    fn declare(&self, shell: &mut Shell, name: &str, value: Option<&str>) -> Result<(), String> {
+        if !is_name(name) {
+            return Err(format!("`{name}': not a valid identifier"));
+        }
+        if let Some(value) = value {
+            shell
+                .vars
+                .set(name, value.to_string())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn unset(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        let mut status = OK;
+        let (functions, variables, names) = match self.args.get(1).map(String::as_str) {
+            Some("-f") => (true, false, &self.args[2..]),
+            Some("-v") => (false, true, &self.args[2..]),
+            _ => (true, true, &self.args[1..]),
+        };
+        for name in names {
+            // without a flag a function is only removed when there is no such variable
+            let is_var = shell.vars.get_var(name).is_some();
+            if functions && !(variables && is_var) {
+                shell.functions.remove(name);
+                continue;
+            }
+            if let Err(e) = shell.vars.unset(name) {
+                writeln!(stderr, "shell: unset: {e}")?;
+                status = NOT_OK;
+            }
+        }
+        Ok(status)
+    }
+
+    fn set(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+
+        // `set -- args` or `set args` replaces the positional parameters
+        if let Some(first) = self.args.get(1) {
+            let args = match first.as_str() {
+                "--" => &self.args[2..],
+                flag if flag.starts_with(['-', '+']) && flag.len() > 1 => {
+                    writeln!(stderr, "shell: set: {flag}: invalid option")?;
+                    return Ok(ChildOrStatus::Status(2));
+                }
+                _ => &self.args[1..],
+            };
+            shell.positional = args.to_vec();
+            return Ok(OK);
+        }
+
+        for (name, var) in shell.vars.iter() {
+            if let Some(value) = &var.value {
+                writeln!(stdout, "{}={}", name, quote(value))?;
+            }
+        }
+        Ok(OK)
+    }
+
+    fn shopt(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let mut args = &self.args[1..];
+        let mut set = None;
+        let mut print = false;
+        while let Some(flag) = args.first().filter(|a| a.starts_with('-')) {
+            match flag.as_str() {
+                "-s" => set = Some(true),
+                "-u" => set = Some(false),
+                "-p" => print = true,
+                _ => {
+                    writeln!(stderr, "shell: shopt: {flag}: invalid option")?;
+                    writeln!(stderr, "shopt: usage: shopt [-psu] [optname ...]")?;
+                    return Ok(ChildOrStatus::Status(2));
+                }
+            }
+            args = &args[1..];
+        }
+
+        let show = |out: &mut dyn Write, name: &str, on: bool| match print {
+            true => writeln!(out, "shopt {} {name}", if on { "-s" } else { "-u" }),
+            false => writeln!(out, "{name:<15}\t{}", if on { "on" } else { "off" }),
+        };
+
+        // without names, list the options, or with -s or -u only those on or off
+        if args.is_empty() {
+            for name in SHOPTS {
+                let on = shell.shopts.contains(*name);
+                if set.is_none_or(|set| set == on) {
+                    show(&mut stdout, name, on)?;
+                }
+            }
+            return Ok(OK);
+        }
+
+        let mut status = OK;
+        for name in args {
+            if !SHOPTS.contains(&name.as_str()) {
+                writeln!(stderr, "shell: shopt: {name}: invalid shell option name")?;
+                status = NOT_OK;
+                continue;
+            }
+            let on = shell.shopts.contains(name);
+            match set {
+                Some(true) => {
+                    shell.shopts.insert(name.clone());
+                }
+                Some(false) => {
+                    shell.shopts.remove(name);
+                }
+                None => {
+                    show(&mut stdout, name, on)?;
+                    if !on {
+                        status = NOT_OK;
+                    }
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    fn let_(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
         if self.args.len() < 2 {
-            write!(stderr, "cd: No such file or directory\n")?;
+            writeln!(stderr, "shell: let: expression expected")?;
             return Ok(NOT_OK);
         }
 
-        let p = if self.args[1] == "~" {
-            PathBuf::from(&home)
-        } else {
-            PathBuf::from(&self.args[1])
+        let mut value = 0;
+        for expr in &self.args[1..] {
+            match arith::eval(expr, shell) {
+                Ok(v) => value = v,
+                Err(e) => {
+                    writeln!(stderr, "shell: let: {e}")?;
+                    return Ok(NOT_OK);
+                }
+            }
+        }
+        // like `((...))`, succeeds when the last expression is not 0
+        Ok(if value != 0 { OK } else { NOT_OK })
+    }
+
+    /// `break [n]` and `continue [n]`, which leave the `n` innermost loops
+    /// or resume the `n`th one
+    fn loop_control(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        let name = &self.name;
+        if shell.loops == 0 {
+            writeln!(
+                stderr,
+                "shell: {name}: only meaningful in a `for', `while', or `until' loop"
+            )?;
+            return Ok(OK);
+        }
+
+        let (n, status) = match self.args.get(1).map(|arg| (arg, arg.parse::<i64>())) {
+            None => (1, OK),
+            Some((_, Ok(n))) if n > 0 => ((n as usize).min(shell.loops), OK),
+            // a bad count still leaves the innermost loop
+            Some((arg, Ok(_))) => {
+                writeln!(stderr, "shell: {name}: {arg}: loop count out of range")?;
+                (1, NOT_OK)
+            }
+            Some((arg, Err(_))) => {
+                writeln!(stderr, "shell: {name}: {arg}: numeric argument required")?;
+                (1, NOT_OK)
+            }
+        };
+        shell.flow = Some(match name.as_str() {
+            "break" => Flow::Break(n),
+            _ => Flow::Continue(n),
+        });
+        Ok(status)
+    }
+
+    /// `alias [-p] [name[=value]...]`, defining aliases or printing them
+    /// in a form that defines them again
+    fn alias(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let args = match self.args.get(1).map(String::as_str) {
+            Some("-p") => &self.args[2..],
+            _ => &self.args[1..],
         };
+        let print =
+            |name: &str, value: &str| format!("alias {name}='{}'", value.replace('\'', "'\\''"));
 
-        if !p.exists() {
-            write!(stderr, "cd: {}: No such file or directory\n", self.args[1])?;
+        if args.is_empty() {
+            for (name, value) in &shell.aliases {
+                writeln!(stdout, "{}", print(name, value))?;
+            }
+            return Ok(OK);
+        }
+
+        let mut status = OK;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, _)) if !is_alias_name(name) => {
+                    writeln!(stderr, "shell: alias: `{name}': invalid alias name")?;
+                    status = NOT_OK;
+                }
+                Some((name, value)) => {
+                    shell.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match shell.aliases.get(arg) {
+                    Some(value) => writeln!(stdout, "{}", print(arg, value))?,
+                    None => {
+                        writeln!(stderr, "shell: alias: {arg}: not found")?;
+                        status = NOT_OK;
+                    }
+                },
+            }
+        }
+        Ok(status)
+    }
+
+    /// `unalias [-a] name...`
+    fn unalias(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        if self.args.get(1).is_some_and(|a| a == "-a") {
+            shell.aliases.clear();
+            return Ok(OK);
+        }
+        if self.args.len() < 2 {
+            writeln!(
+                stderr,
+                "shell: unalias: usage: unalias [-a] name [name ...]"
+            )?;
+            return Ok(ChildOrStatus::Status(2));
+        }
+
+        let mut status = OK;
+        for name in &self.args[1..] {
+            if shell.aliases.remove(name).is_none() {
+                writeln!(stderr, "shell: unalias: {name}: not found")?;
+                status = NOT_OK;
+            }
+        }
+        Ok(status)
+    }
+
+    /// `local name[=value]...`, giving the running function its own copy of the
+    /// variables until it returns
+    fn local(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        let Some(frame) = shell.locals.last() else {
+            writeln!(stderr, "shell: local: can only be used in a function")?;
             return Ok(NOT_OK);
+        };
+        let mut declared = frame.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+
+        let mut status = OK;
+        for arg in &self.args[1..] {
+            let (name, value) = split_assignment(arg);
+            if !is_name(name) {
+                writeln!(stderr, "shell: local: `{name}': not a valid identifier")?;
+                status = NOT_OK;
+                continue;
+            }
+            let old = shell.vars.get_var(name).cloned();
+            if old.as_ref().is_some_and(|v| v.readonly) {
+                writeln!(stderr, "shell: local: {name}: readonly variable")?;
+                status = NOT_OK;
+                continue;
+            }
+
+            // a second `local` for the same name keeps the value saved by the first
+            if !declared.iter().any(|n| n == name) {
+                declared.push(name.to_string());
+                if let Some(frame) = shell.locals.last_mut() {
+                    frame.push((name.to_string(), old));
+                }
+                shell.vars.restore(name, Some(Var::default()));
+            }
+            if let Some(value) = value {
+                let _ = shell.vars.set(name, value.to_string());
+            }
         }
+        Ok(status)
+    }
 
-        shell.pwd = p.canonicalize()?;
-        std::env::set_current_dir(p)?;
+    /// `return [n]`, leaving the running function or sourced file with status `n`,
+    /// or the status of the last command
+    fn return_(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        if shell.locals.is_empty() && shell.sources == 0 {
+            writeln!(
+                stderr,
+                "shell: return: can only `return' from a function or sourced script"
+            )?;
+            return Ok(ChildOrStatus::Status(2));
+        }
 
-        Ok(OK)
+        let status = match self.args.get(1) {
+            None => shell.status_code,
+            Some(arg) => match arg.parse::<i64>() {
+                Ok(n) => n as u8 as i32,
+                Err(_) => {
+                    writeln!(stderr, "shell: return: {arg}: numeric argument required")?;
+                    2
+                }
+            },
+        };
+        shell.flow = Some(Flow::Return);
+        Ok(ChildOrStatus::Status(status))
+    }
+
+    /// `jobs [-lp] [jobspec...]`, listing jobs with their pids when `-l` is
+    /// given, or only their process groups with `-p`. Finished jobs are
+    /// removed once they have been listed.
+    fn jobs(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let mut long = false;
+        let mut pids = false;
+        let mut args = &self.args[1..];
+        while let Some(flag) = args.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            for c in flag[1..].chars() {
+                match c {
+                    'l' => long = true,
+                    'p' => pids = true,
+                    _ => {
+                        writeln!(stderr, "shell: jobs: -{c}: invalid option")?;
+                        writeln!(stderr, "jobs: usage: jobs [-lp] [jobspec ...]")?;
+                        return Ok(ChildOrStatus::Status(2));
+                    }
+                }
+            }
+            args = &args[1..];
+        }
+
+        shell.jobs.update();
+        let mut status = OK;
+        let mut ids = vec![];
+        for spec in args {
+            match shell.jobs.resolve(spec) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    writeln!(stderr, "shell: jobs: {e}")?;
+                    status = NOT_OK;
+                }
+            }
+        }
+        if args.is_empty() {
+            ids = shell.jobs.ids();
+        }
+
+        for id in ids {
+            let Some(job) = shell.jobs.get(id) else {
+                continue;
+            };
+            match pids {
+                true => writeln!(stdout, "{}", job.pgid)?,
+                false => writeln!(stdout, "{}", shell.jobs.format(job, long))?,
+            }
+            if job.state().is_done() {
+                shell.jobs.remove(id);
+            }
+        }
+        Ok(status)
+    }
+
+    /// `fg [jobspec]`, continuing a job in the foreground and waiting for it
+    fn fg(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let id = match job_arg(shell, self.args.get(1)) {
+            Ok(id) => id,
+            Err(e) => {
+                writeln!(stderr, "shell: fg: {e}")?;
+                return Ok(NOT_OK);
+            }
+        };
+        let Some(job) = shell.jobs.get(id) else {
+            return Ok(NOT_OK);
+        };
+        writeln!(stdout, "{}", job.command)?;
+        stdout.flush()?;
+
+        match shell.run_foreground(id, true) {
+            Ok(state) => {
+                if let JobState::Killed(signo, core) = state {
+                    report_signal(signo, core);
+                    if signo == libc::SIGINT {
+                        writeln!(stderr)?;
+                    }
+                }
+                Ok(ChildOrStatus::Status(state.status()))
+            }
+            Err(e) => {
+                writeln!(stderr, "shell: fg: {}", ShellError::Io(e))?;
+                Ok(NOT_OK)
+            }
+        }
+    }
+
+    /// `bg [jobspec...]`, continuing stopped jobs in the background
+    fn bg(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let specs = match self.args.len() {
+            1 => vec![None],
+            _ => self.args[1..].iter().map(Some).collect(),
+        };
+        let mut status = OK;
+        for spec in specs {
+            let id = match job_arg(shell, spec) {
+                Ok(id) => id,
+                Err(e) => {
+                    writeln!(stderr, "shell: bg: {e}")?;
+                    status = NOT_OK;
+                    continue;
+                }
+            };
+            if shell
+                .jobs
+                .get(id)
+                .is_some_and(|job| job.state() == JobState::Running)
+            {
+                writeln!(stderr, "shell: bg: job {id} already in background")?;
+                continue;
+            }
+            shell.continue_job(id)?;
+            if let Some(job) = shell.jobs.get(id) {
+                let mark = shell.jobs.mark(id);
+                writeln!(stdout, "[{id}]{mark} {}", job.describe_command())?;
+            }
+        }
+        Ok(status)
+    }
+
+    /// `wait [-n] [jobspec|pid...]`, waiting for the given jobs, or all of
+    /// them, to finish, or for the next one to finish with `-n`
+    fn wait(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        let mut args = &self.args[1..];
+        let next = args.first().is_some_and(|a| a == "-n");
+        if next {
+            args = &args[1..];
+        }
+
+        let mut ids = vec![];
+        let mut status = 0;
+        for arg in args {
+            let id = if arg.starts_with('%') {
+                shell.jobs.resolve(arg)
+            } else {
+                match arg.parse::<libc::pid_t>() {
+                    Ok(pid) => shell
+                        .jobs
+                        .find_pid(pid)
+                        .ok_or_else(|| format!("pid {pid} is not a child of this shell")),
+                    Err(_) => {
+                        writeln!(stderr, "shell: wait: `{arg}': not a pid or valid job spec")?;
+                        status = 1;
+                        continue;
+                    }
+                }
+            };
+            match id {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    writeln!(stderr, "shell: wait: {e}")?;
+                    status = 127;
+                }
+            }
+        }
+        if args.is_empty() {
+            ids = shell.jobs.ids();
+        }
+
+        let r = match next {
+            true => wait_next(shell, &ids),
+            false => {
+                let mut r = Ok(status);
+                for id in ids {
+                    // a stopped job would never finish
+                    if let Some(JobState::Stopped(_)) = shell.jobs.get(id).map(|job| job.state()) {
+                        continue;
+                    }
+                    r = shell.wait_job(id).map(JobState::status);
+                    if r.is_err() {
+                        break;
+                    }
+                }
+                // waiting for every job always succeeds
+                r.map(|status| if args.is_empty() { 0 } else { status })
+            }
+        };
+        match r {
+            Ok(status) => Ok(ChildOrStatus::Status(status)),
+            // a trapped signal ends the wait, and its trap runs next
+            Err(e) if e.kind() == ErrorKind::Interrupted => {
+                let signo = signals::pending().unwrap_or(0);
+                Ok(ChildOrStatus::Status(128 + signo))
+            }
+            Err(e) => {
+                writeln!(stderr, "shell: wait: {}", ShellError::Io(e))?;
+                Ok(NOT_OK)
+            }
+        }
+    }
+
+    /// `disown [-ahr] [jobspec...]`, removing jobs from the table so the shell
+    /// forgets about them, all of them with `-a` or the running ones with `-r`.
+    /// With `-h` they stay in the table.
+    fn disown(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, _, mut stderr) = self.take_io();
+        let (mut all, mut running, mut keep) = (false, false, false);
+        let mut args = &self.args[1..];
+        while let Some(flag) = args.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            for c in flag[1..].chars() {
+                match c {
+                    'a' => all = true,
+                    'r' => running = true,
+                    'h' => keep = true,
+                    _ => {
+                        writeln!(stderr, "shell: disown: -{c}: invalid option")?;
+                        writeln!(stderr, "disown: usage: disown [-h] [-ar] [jobspec ...]")?;
+                        return Ok(ChildOrStatus::Status(2));
+                    }
+                }
+            }
+            args = &args[1..];
+        }
+
+        shell.jobs.update();
+        let mut status = OK;
+        let ids = if all || (running && args.is_empty()) {
+            shell.jobs.ids()
+        } else {
+            let specs = match args.len() {
+                0 => vec![None],
+                _ => args.iter().map(Some).collect(),
+            };
+            let mut ids = vec![];
+            for spec in specs {
+                match job_arg(shell, spec) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => {
+                        writeln!(stderr, "shell: disown: {e}")?;
+                        status = NOT_OK;
+                    }
+                }
+            }
+            ids
+        };
+        for id in ids {
+            let state = shell.jobs.get(id).map(|job| job.state());
+            if running && state != Some(JobState::Running) {
+                continue;
+            }
+            // the shell never sends SIGHUP to its jobs, so a job kept
+            // with `-h` needs no further marking
+            if !keep {
+                shell.jobs.remove(id);
+            }
+        }
+        Ok(status)
+    }
+
+    /// `trap [-lp] [[command] signal...]`, setting the command to run when the shell
+    /// gets a signal or reaches EXIT, ERR, DEBUG or RETURN, ignoring the signal if
+    /// the command is empty and resetting it if it is `-` or left out. Without
+    /// a command, or with `-p`, it prints the traps instead.
+    fn trap(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let (mut list, mut print) = (false, false);
+        let mut args = &self.args[1..];
+        while let Some(flag) = args.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            args = &args[1..];
+            if flag == "--" {
+                break;
+            }
+            for c in flag[1..].chars() {
+                match c {
+                    'l' => list = true,
+                    'p' => print = true,
+                    _ => {
+                        writeln!(stderr, "shell: trap: -{c}: invalid option")?;
+                        writeln!(stderr, "trap: usage: trap [-lp] [[arg] signal_spec ...]")?;
+                        return Ok(ChildOrStatus::Status(2));
+                    }
+                }
+            }
+        }
+        if list {
+            write_signals(&mut stdout)?;
+            return Ok(OK);
+        }
+
+        let mut status = OK;
+        let mut traps = vec![];
+        // a lone signal, or signals given by number only, are reset
+        let command = match args {
+            _ if print || args.len() < 2 => None,
+            [first, ..] if !first.is_empty() && first.chars().all(|c| c.is_ascii_digit()) => None,
+            [first, rest @ ..] => {
+                let command = Some(first.clone());
+                args = rest;
+                command
+            }
+            [] => None,
+        };
+        for spec in args {
+            match Trap::parse(spec) {
+                Some(trap) => traps.push(trap),
+                None => {
+                    writeln!(stderr, "shell: trap: {spec}: invalid signal specification")?;
+                    status = NOT_OK;
+                }
+            }
+        }
+
+        if print || args.is_empty() {
+            let all = args.is_empty();
+            for (trap, command) in shell.traps.iter() {
+                if all || traps.contains(&trap) {
+                    let command = command.replace('\'', "'\\''");
+                    writeln!(stdout, "trap -- '{command}' {trap}")?;
+                }
+            }
+            return Ok(status);
+        }
+        for trap in traps {
+            match command.as_deref() {
+                None | Some("-") => shell.traps.reset(trap, shell.terminal.is_some()),
+                Some(command) => shell.traps.set(trap, command.to_string()),
+            }
+        }
+        Ok(status)
+    }
+
+    /// `kill [-s sigspec | -n signum | -sigspec] pid|jobspec...`, sending a signal,
+    /// SIGTERM by default, to processes, to process groups given as negative pids,
+    /// or to jobs. `kill -l` lists the signals, or translates the names and numbers
+    /// it is given, including the statuses of commands killed by a signal.
+    fn kill(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
+        let (_, mut stdout, mut stderr) = self.take_io();
+        let mut args = &self.args[1..];
+        let mut signo = libc::SIGTERM;
+        let spec = match args.first().map(String::as_str) {
+            Some("-l" | "-L") => return kill_list(&args[1..], &mut stdout, &mut stderr),
+            Some("-s" | "-n") => {
+                let Some(spec) = args.get(1) else {
+                    writeln!(
+                        stderr,
+                        "shell: kill: {}: option requires an argument",
+                        args[0]
+                    )?;
+                    return Ok(NOT_OK);
+                };
+                args = &args[2..];
+                Some(spec.as_str())
+            }
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 && flag != "--" => {
+                args = &args[1..];
+                Some(&flag[1..])
+            }
+            _ => None,
+        };
+        // `--` ends the options, so the targets can be negative
+        if args.first().is_some_and(|arg| arg == "--") {
+            args = &args[1..];
+        }
+        if let Some(spec) = spec {
+            // 0 only checks whether the processes exist
+            match signals::number(spec).or((spec == "0").then_some(0)) {
+                Some(n) => signo = n,
+                None => {
+                    writeln!(stderr, "shell: kill: {spec}: invalid signal specification")?;
+                    return Ok(NOT_OK);
+                }
+            }
+        }
+        if args.is_empty() {
+            writeln!(
+                stderr,
+                "kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]"
+            )?;
+            return Ok(ChildOrStatus::Status(2));
+        }
+
+        shell.jobs.update();
+        let mut status = OK;
+        for arg in args {
+            let target = match arg.starts_with('%') {
+                true => shell.jobs.resolve(arg).map(|id| {
+                    let pgid = shell.jobs.get(id).map_or(0, |job| job.pgid);
+                    -pgid
+                }),
+                false => arg
+                    .parse::<libc::pid_t>()
+                    .map_err(|_| format!("{arg}: arguments must be process or job IDs")),
+            };
+            let target = match target {
+                Ok(target) => target,
+                Err(e) => {
+                    writeln!(stderr, "shell: kill: {e}")?;
+                    status = NOT_OK;
+                    continue;
+                }
+            };
+            // SAFETY: kill only sends a signal
+            if unsafe { libc::kill(target, signo) } < 0 {
+                let e = ShellError::Io(std::io::Error::last_os_error());
+                writeln!(stderr, "shell: kill: ({arg}) - {e}")?;
+                status = NOT_OK;
+                continue;
+            }
+            // a stopped job only gets to handle a request to terminate once it's continued
+            let stopped = shell
+                .jobs
+                .find_pid(target.abs())
+                .and_then(|id| shell.jobs.get(id))
+                .filter(|job| matches!(job.state(), JobState::Stopped(_)))
+                .map(|job| job.pgid);
+            if let (Some(pgid), libc::SIGTERM | libc::SIGHUP) = (stopped, signo) {
+                // SAFETY: kill only sends a signal
+                unsafe { libc::kill(-pgid, libc::SIGCONT) };
+                shell.jobs.continued(-pgid);
+            }
+            if signo == libc::SIGCONT {
+                shell.jobs.continued(target);
+            }
+        }
+        Ok(status)
     }
 
     fn history(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
@@ -134,43 +1188,50 @@ impl Command {
             }
         }
 
-        for i in history.len() - lim..history.len() {
-            write!(stdout, "    {}  {}\n", i + 1, history[i])?;
+        for (i, line) in history.iter().enumerate().skip(history.len() - lim) {
+            writeln!(stdout, "    {}  {}", i + 1, line)?;
         }
 
         Ok(OK)
     }
 
-    fn pwd(&mut self, pwd: &PathBuf) -> std::io::Result<ChildOrStatus> {
+    fn pwd(&mut self, pwd: &Path) -> std::io::Result<ChildOrStatus> {
         let (_, mut stdout, _) = self.take_io();
-        write!(stdout, "{}\n", pwd.to_str().unwrap())?;
+        writeln!(stdout, "{}", pwd.display())?;
         Ok(OK)
     }
 
-    fn exit(&mut self) -> std::io::Result<ChildOrStatus> {
+    /// `exit [n]`, ending the shell with status `n` or the status of the last command,
+    /// after running the EXIT trap
+    fn exit(&mut self, shell: &mut Shell) -> std::io::Result<ChildOrStatus> {
         let (_, _, mut stderr) = self.take_io();
-
-        if self.args.len() < 2 {
-            write!(stderr, "Usage: exit <exit_code>")?;
-            return Ok(NOT_OK);
-        }
-        let exit_code = self.args[1].parse::<i32>();
-        match exit_code {
-            Ok(code) => std::process::exit(code),
-            Err(_) => {
-                write!(stderr, "invalid error code")?;
-                Ok(NOT_OK)
-            }
+        let code = match self.args.get(1) {
+            None => shell.status_code,
+            Some(arg) => match arg.parse::<i64>() {
+                Ok(n) => n as u8 as i32,
+                Err(_) => {
+                    writeln!(stderr, "shell: exit: {arg}: numeric argument required")?;
+                    2
+                }
+            },
+        };
+        let code = shell.run_exit_trap(code);
+        std::io::stdout().flush()?;
+        if shell.subshell {
+            // SAFETY: like at the end of `Shell::fork`, _exit skips the
+            // exit handlers, which belong to the parent
+            unsafe { libc::_exit(code) }
         }
+        std::process::exit(code)
     }
 
     fn echo(&mut self) -> std::io::Result<ChildOrStatus> {
         let (_, mut stdout, _) = self.take_io();
-        write!(stdout, "{}\n", self.args[1..].join(" ").trim())?;
+        writeln!(stdout, "{}", self.args[1..].join(" "))?;
         Ok(OK)
     }
 
-    fn find_executable(&self, cmd: &String, path: &Vec<PathBuf>) -> Option<PathBuf> {
+    fn find_executable(&self, cmd: &str, path: &[PathBuf]) -> Option<PathBuf> {
         for p in path {
             let entries = match p.read_dir() {
                 Ok(r) => r,
@@ -196,3 +1257,111 @@ impl Command {
         None
     }
 }
+
+/// Finds the job an argument of `fg`, `bg` or `disown` refers to, the current job without one
+fn job_arg(shell: &Shell, spec: Option<&String>) -> Result<usize, String> {
+    match spec {
+        Some(spec) => shell.jobs.resolve(spec),
+        None => shell
+            .jobs
+            .current()
+            .ok_or_else(|| "current: no such job".to_string()),
+    }
+}
+
+/// Waits until one of the jobs in `ids` finishes, removes it from
+/// the table and returns its status, or 127 if none are running
+fn wait_next(shell: &mut Shell, ids: &[usize]) -> std::io::Result<i32> {
+    loop {
+        shell.jobs.update();
+        let done = ids.iter().copied().find(|id| {
+            let state = shell.jobs.get(*id).map(|job| job.state());
+            state.is_some_and(JobState::is_done)
+        });
+        if let Some(id) = done {
+            return shell.wait_job(id).map(JobState::status);
+        }
+        let running = ids.iter().any(|id| {
+            let state = shell.jobs.get(*id).map(|job| job.state());
+            state == Some(JobState::Running)
+        });
+        if !running {
+            return Ok(127);
+        }
+
+        let mut status = 0;
+        // SAFETY: waitpid only writes to `status`
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::WUNTRACED) };
+        if pid < 0 {
+            let e = std::io::Error::last_os_error();
+            // none of the jobs are children of this process, e.g. in a forked copy
+            if e.raw_os_error() == Some(libc::ECHILD) {
+                return Ok(127);
+            }
+            return Err(e);
+        }
+        shell.jobs.record(pid, status);
+    }
+}
+
+/// Lists the signals like `trap -l` and `kill -l` do, five to a line
+fn write_signals(out: &mut impl Write) -> std::io::Result<()> {
+    let signals = signals::all().collect::<Vec<_>>();
+    for line in signals.chunks(5) {
+        let line = line
+            .iter()
+            .map(|(signo, name)| format!("{signo:2}) SIG{name}"))
+            .collect::<Vec<_>>();
+        // a line that isn't full still ends with a tab
+        let end = if line.len() < 5 { "\t" } else { "" };
+        writeln!(out, "{}{end}", line.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// `kill -l [sigspec...]`, listing the signals, or printing the name of each
+/// signal given by number, or by the status of a command it killed, and the
+/// number of each given by name
+fn kill_list(
+    args: &[String],
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> std::io::Result<ChildOrStatus> {
+    if args.is_empty() {
+        write_signals(stdout)?;
+        return Ok(OK);
+    }
+    let mut status = OK;
+    for arg in args {
+        let found = match arg.parse::<i32>() {
+            // a status above 128 is that of a command killed by a signal
+            Ok(n) => signals::name(if n > 128 { n - 128 } else { n }).map(str::to_string),
+            Err(_) => signals::number(arg).map(|signo| signo.to_string()),
+        };
+        match found {
+            Some(found) => writeln!(stdout, "{found}")?,
+            None => {
+                writeln!(stderr, "shell: kill: {arg}: invalid signal specification")?;
+                status = NOT_OK;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Whether `name` can be defined as an alias, which excludes
+/// characters that would be taken apart when the line is read
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "/$`=\\'\"|&;()<>".contains(c))
+}
+
+/// Splits `NAME=value` into its name and value
+fn split_assignment(arg: &str) -> (&str, Option<&str>) {
+    match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg, None),
+    }
+}
@@ -1,10 +1,13 @@
-use crate::shell_io::{Input, Output};
-use regex::{Captures, Regex};
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-
-/// This module will contain the Command struct
-/// and the parsing logic along with it
+/// This module contains the Command struct which is
+/// built from a parsed simple command and then executed
+use crate::ast::{Redirect, RedirectOp};
+use crate::error::ShellError;
+use crate::shell::Shell;
+use crate::shell_io::{dup_raw_fd, temp_file, Input, Output, SavedFds};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Read, Write};
+use std::os::fd::{OwnedFd, RawFd};
 
 pub struct Command {
     pub name: String,
@@ -12,6 +15,12 @@ pub struct Command {
     pub stdin: Input,
     pub stdout: Output,
     pub stderr: Output,
+    /// Redirections of descriptors above 2, `None` meaning closed.
+    /// These are only visible to child processes.
+    pub fds: BTreeMap<u32, Option<OwnedFd>>,
+    /// Whether an external program replaces the shell instead of running as its
+    /// child, because the shell has nothing left to do after it
+    pub exec: bool,
 }
 
 impl Command {
@@ -22,6 +31,8 @@ impl Command {
             stdin: Input::Stdin,
             stdout: Output::Stdout,
             stderr: Output::Stderr,
+            fds: BTreeMap::new(),
+            exec: false,
         }
     }
 
@@ -30,14 +41,86 @@ impl Command {
         self
     }
 
-    pub fn with_stderr(mut self, stderr: Output) -> Command {
-        self.stderr = stderr;
-        self
+    /// Returns a duplicate of whatever `fd` currently refers to
+    fn get_fd(&self, fd: u32) -> std::io::Result<OwnedFd> {
+        match fd {
+            0 => self.stdin.try_clone_fd(),
+            1 => self.stdout.try_clone_fd(),
+            2 => self.stderr.try_clone_fd(),
+            _ => match self.fds.get(&fd) {
+                Some(Some(f)) => f.try_clone(),
+                Some(None) => Err(Error::from_raw_os_error(libc::EBADF)),
+                None => dup_raw_fd(fd as i32),
+            },
+        }
     }
 
-    pub fn with_stdout(mut self, stdout: Output) -> Command {
-        self.stdout = stdout;
-        self
+    /// Points `fd` at the given file, or closes it when `None`
+    fn set_fd(&mut self, fd: u32, file: Option<OwnedFd>) {
+        match (fd, file) {
+            (0, Some(f)) => self.stdin = Input::File(File::from(f)),
+            (0, None) => self.stdin = Input::Closed,
+            (1, Some(f)) => self.stdout = Output::File(File::from(f)),
+            (1, None) => self.stdout = Output::Closed,
+            (2, Some(f)) => self.stderr = Output::File(File::from(f)),
+            (2, None) => self.stderr = Output::Closed,
+            (_, f) => {
+                self.fds.insert(fd, f);
+            }
+        }
+    }
+
+    /// Makes `fd` a copy of `source`, as in `fd>&source`
+    fn dup_fd(&mut self, source: u32, fd: u32) -> std::io::Result<()> {
+        match (source, fd) {
+            // keep stdout and stderr as they are so builtins keep
+            // writing through the shell's own buffered handles
+            (1, 2) => self.stderr = self.stdout.try_clone()?,
+            (2, 1) => self.stdout = self.stderr.try_clone()?,
+            _ => {
+                let f = self.get_fd(source)?;
+                if source != fd {
+                    self.set_fd(fd, Some(f));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Places the redirected descriptors on the shell's own, for commands
+    /// that run inside the shell, and returns what to restore afterwards
+    pub fn install(&mut self) -> std::io::Result<SavedFds> {
+        // every source is duplicated before any descriptor is replaced,
+        // so that e.g. `2>&1 >file` still sees the original stdout
+        let mut fds = vec![];
+        match self.stdin {
+            Input::Stdin => {}
+            Input::Closed => fds.push((0, None)),
+            _ => fds.push((0, Some(self.stdin.try_clone_fd()?))),
+        }
+        match self.stdout {
+            Output::Stdout => {}
+            Output::Closed => fds.push((1, None)),
+            _ => fds.push((1, Some(self.stdout.try_clone_fd()?))),
+        }
+        match self.stderr {
+            Output::Stderr => {}
+            Output::Closed => fds.push((2, None)),
+            _ => fds.push((2, Some(self.stderr.try_clone_fd()?))),
+        }
+        for (fd, f) in std::mem::take(&mut self.fds) {
+            fds.push((fd as RawFd, f));
+        }
+        SavedFds::replace(fds)
+    }
+
+    /// The descriptors of the pipes the command reads from and writes to
+    pub fn pipe_fds(&self) -> Vec<RawFd> {
+        self.stdin
+            .pipe_fd()
+            .into_iter()
+            .chain(self.stdout.pipe_fd())
+            .collect()
     }
 
     pub fn take_io(&mut self) -> (Box<dyn Read>, Box<dyn Write>, Box<dyn Write>) {
@@ -48,85 +131,91 @@ impl Command {
     }
 }
 
-impl From<String> for Command {
-    fn from(value: String) -> Self {
-        let re =
-            Regex::new(r#"(?:[^'"\s\\]|\\.)+|'(?:[^'\\]|\\.)*'|"(?:[^"\\]|\\.)*"| +"#).unwrap();
-        let back = Regex::new(r#"\\(.)"#).unwrap();
-        let back_quotes = Regex::new(r#"\\(')"#).unwrap();
-        let back_double_quotes = Regex::new(r#"\\([`$"\\\n])"#).unwrap();
-
-        let mut args = vec![];
-        let mut buf = String::new();
-        for val in re.captures_iter(&value) {
-            // println!("v: {:?}", val.get_match().as_str());
-            let v = val.get_match().as_str().trim();
-            if v.is_empty() {
-                args.push(buf);
-                buf = String::new();
-                continue;
-            }
-            let f = v.chars().nth(0).unwrap();
-            let l = v.chars().last().unwrap();
-            if f == '\'' && l == '\'' {
-                let v = back_quotes.replace_all(v, |caps: &Captures| format!("{}", &caps[1]));
-                let v = v[1..v.len() - 1].to_string();
-                buf += &v;
-            } else if f == '"' && l == '"' {
-                let v =
-                    back_double_quotes.replace_all(v, |caps: &Captures| format!("{}", &caps[1]));
-                let v = v[1..v.len() - 1].to_string();
-                buf += &v;
-            } else {
-                let v = back.replace_all(v, |caps: &Captures| format!("{}", &caps[1]));
-                buf += &v;
-            }
-        }
+impl From<Vec<String>> for Command {
+    fn from(args: Vec<String>) -> Self {
+        Command::new(args.first().cloned().unwrap_or_default()).with_args(args)
+    }
+}
 
-        if !buf.trim().is_empty() {
-            args.push(buf);
+impl Command {
+    /// Applies redirections in order on top of the current descriptors,
+    /// so they take precedence over pipes set up for a pipeline
+    pub fn redirect(
+        &mut self,
+        redirects: &[Redirect],
+        shell: &mut Shell,
+    ) -> Result<(), ShellError> {
+        for redirect in redirects {
+            let target = shell.expand_word(&redirect.target)?;
+            self.apply_redirect(redirect, &target)
+                .map_err(|e| ShellError::Redirect(target, e))?;
         }
+        Ok(())
+    }
 
-        let mut upto = args.len();
-        let mut cmd = Command::new(args[0].clone());
-
-        if let Some(idx) = args
-            .iter()
-            .position(|x| [">", "1>", ">>", "1>>"].contains(&x.as_str()))
-        {
-            upto = upto.min(idx);
-            let mut options = OpenOptions::new();
-            options.create(true);
-
-            if args[idx] == ">>" || args[idx] == "1>>" {
-                options.append(true);
-            } else {
-                options.write(true).truncate(true);
+    fn apply_redirect(&mut self, redirect: &Redirect, target: &str) -> std::io::Result<()> {
+        let fd = redirect.fd.unwrap_or(redirect.op.default_fd());
+
+        let file = match redirect.op {
+            RedirectOp::Write => write_file(target, false)?,
+            RedirectOp::Append => write_file(target, true)?,
+            RedirectOp::Read => File::open(target)?,
+            RedirectOp::ReadWrite => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(target)?,
+            RedirectOp::HereDoc => temp_file(target.as_bytes())?,
+            RedirectOp::HereString => temp_file(format!("{target}\n").as_bytes())?,
+            RedirectOp::WriteAll | RedirectOp::AppendAll => {
+                let file = write_file(target, redirect.op == RedirectOp::AppendAll)?;
+                self.set_fd(1, Some(file.try_clone()?.into()));
+                self.set_fd(2, Some(file.into()));
+                return Ok(());
             }
-
-            let stdout = options.open(args[idx + 1].clone()).unwrap();
-            cmd = cmd.with_stdout(Output::File(stdout));
-        }
-
-        if let Some(idx) = args
-            .iter()
-            .position(|x| ["2>", "2>>"].contains(&x.as_str()))
-        {
-            upto = upto.min(idx);
-            let mut options = OpenOptions::new();
-            options.create(true);
-
-            if args[idx] == "2>>" {
-                options.append(true);
-            } else {
-                options.write(true).truncate(true);
+            RedirectOp::DupOut | RedirectOp::DupIn => {
+                if target == "-" {
+                    self.set_fd(fd, None);
+                } else if let Ok(source) = target.parse() {
+                    self.dup_fd(source, fd)?;
+                } else if redirect.op == RedirectOp::DupOut && redirect.fd.is_none() {
+                    let file = write_file(target, false)?;
+                    self.set_fd(1, Some(file.try_clone()?.into()));
+                    self.set_fd(2, Some(file.into()));
+                } else {
+                    return Err(Error::other("ambiguous redirect"));
+                }
+                return Ok(());
             }
+        };
 
-            let stderr = options.open(args[idx + 1].clone()).unwrap();
+        self.set_fd(fd, Some(file.into()));
+        Ok(())
+    }
 
-            cmd = cmd.with_stderr(Output::File(stderr));
-        }
+    /// Prints an error the command ran into on its stderr, or on the shell's own
+    /// if that was already handed out, after the `location` of the command if
+    /// it was read from a file
+    pub fn report(&mut self, e: &ShellError, location: Option<&str>) {
+        let mut stderr = match self.stderr {
+            Output::None => Box::new(std::io::stderr()),
+            _ => self.stderr.take_write(),
+        };
+        let _ = match location {
+            Some(location) => writeln!(stderr, "shell: {location}: {e}"),
+            None => writeln!(stderr, "shell: {e}"),
+        };
+    }
+}
 
-        cmd.with_args(args[..upto].to_vec())
+fn write_file(path: &str, append: bool) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
     }
+    options.open(path)
 }
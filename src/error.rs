@@ -0,0 +1,110 @@
+/// This module contains the error type used while parsing
+/// and executing commands, along with the exit status each
+/// kind of error results in
+use crate::parser::ParseError;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+#[derive(Debug)]
+pub enum ShellError {
+    Syntax(ParseError),
+    /// A redirection target could not be opened or duplicated
+    Redirect(String, io::Error),
+    CommandNotFound(String),
+    /// The command exists but could not be executed
+    NotExecutable(String, io::Error),
+    /// A builtin failed, usually while writing its output
+    Builtin(String, io::Error),
+    /// An attempt to assign or unset a read-only variable
+    Readonly(String),
+    /// A malformed `${...}` expansion
+    BadSubstitution(String),
+    /// A parameter expansion failed, e.g. `${NAME:?message}` on an unset variable
+    Param(String, String),
+    /// An arithmetic expression is malformed or can't be evaluated, e.g. on division by 0
+    Arith(String, String),
+    /// A pattern matched no files while `failglob` is set
+    NoMatch(String),
+    /// A file to read commands from could not be read
+    Read(String, io::Error),
+    /// A function was called while the given number of calls were already running
+    Nesting(String, usize),
+    /// A file was sourced while the given number of sourced files were already running
+    SourceNesting(String, usize),
+    /// A command in the foreground was interrupted from the keyboard,
+    /// which leaves the rest of the commands on the line
+    Interrupted,
+    Io(io::Error),
+}
+
+impl ShellError {
+    /// The exit status a command failing with this error ends with
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Syntax(_) => 2,
+            ShellError::Redirect(..) => 1,
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::NotExecutable(_, e) if e.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::NotExecutable(..) => 126,
+            ShellError::Builtin(..) => 1,
+            ShellError::Readonly(_) => 1,
+            ShellError::BadSubstitution(_) => 1,
+            ShellError::Param(..) => 1,
+            ShellError::Arith(..) => 1,
+            ShellError::NoMatch(_) => 1,
+            ShellError::Read(..) => 1,
+            ShellError::Nesting(..) => 1,
+            ShellError::SourceNesting(..) => 1,
+            ShellError::Interrupted => 128 + libc::SIGINT,
+            ShellError::Io(_) => 1,
+        }
+    }
+}
+
+impl Display for ShellError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellError::Syntax(e) => write!(f, "{e}"),
+            ShellError::Redirect(target, e) => write!(f, "{target}: {}", describe(e)),
+            ShellError::CommandNotFound(name) => write!(f, "{name}: command not found"),
+            ShellError::NotExecutable(name, e) => write!(f, "{name}: {}", describe(e)),
+            ShellError::Builtin(name, e) => write!(f, "{name}: write error: {}", describe(e)),
+            ShellError::Readonly(name) => write!(f, "{name}: readonly variable"),
+            ShellError::BadSubstitution(s) => write!(f, "{s}: bad substitution"),
+            ShellError::Param(name, msg) => write!(f, "{name}: {msg}"),
+            ShellError::Arith(expr, msg) => write!(f, "{expr}: {msg}"),
+            ShellError::NoMatch(pattern) => write!(f, "no match: {pattern}"),
+            ShellError::Read(name, e) => write!(f, "{name}: {}", describe(e)),
+            ShellError::Nesting(name, max) => {
+                write!(f, "{name}: maximum function nesting level exceeded ({max})")
+            }
+            ShellError::SourceNesting(name, max) => {
+                write!(f, "{name}: maximum source nesting level exceeded ({max})")
+            }
+            ShellError::Interrupted => write!(f, "interrupted"),
+            ShellError::Io(e) => write!(f, "{}", describe(e)),
+        }
+    }
+}
+
+impl From<ParseError> for ShellError {
+    fn from(value: ParseError) -> Self {
+        ShellError::Syntax(value)
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(value: io::Error) -> Self {
+        ShellError::Io(value)
+    }
+}
+
+/// Formats an io error like the C library does, without the
+/// "(os error N)" suffix that std appends
+fn describe(e: &io::Error) -> String {
+    let s = e.to_string();
+    match s.find(" (os error") {
+        Some(i) => s[..i].to_string(),
+        None => s,
+    }
+}
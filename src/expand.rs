@@ -0,0 +1,645 @@
+/// This module contains the expansions applied to parsed
+/// words right before a command is executed
+use crate::arith;
+use crate::ast::{Param, ParamOp, ReplaceMode, Word, WordPart};
+use crate::brace;
+use crate::builtins::exit_status;
+use crate::error::ShellError;
+use crate::glob::{self, Pattern};
+use crate::parser::Parser;
+use crate::shell::Shell;
+use crate::shell_io::{self, Output};
+use crate::vars::is_name;
+use std::ffi::{CStr, CString};
+use std::io::{pipe, Read};
+
+/// A field a word expands to, along with the pattern it
+/// forms for pathname expansion
+#[derive(Default)]
+struct Field {
+    text: String,
+    /// The text with quoted pattern characters escaped
+    pattern: String,
+}
+
+/// Collects the fields a word expands to
+struct Fields {
+    /// The characters separating fields in the results of unquoted expansions
+    ifs: String,
+    fields: Vec<Field>,
+    /// The field being built, `None` between fields
+    current: Option<Field>,
+    /// Whether IFS whitespace just ended a field, in which case
+    /// it forms a single separator with a following `:` or the like
+    after_space: bool,
+}
+
+impl Fields {
+    fn new(ifs: &str) -> Fields {
+        Fields {
+            ifs: ifs.to_string(),
+            fields: vec![],
+            current: None,
+            after_space: false,
+        }
+    }
+
+    /// Adds text to the current field. Quoted text starts a field even when
+    /// empty, while an unquoted expansion with an empty result vanishes.
+    fn push(&mut self, s: &str, quoted: bool) {
+        if s.is_empty() && !quoted {
+            return;
+        }
+        self.after_space = false;
+        let field = self.current.get_or_insert_default();
+        field.text.push_str(s);
+        match quoted {
+            true => field.pattern.push_str(&glob::escape(s)),
+            false => field.pattern.push_str(s),
+        }
+    }
+
+    /// Adds the result of an unquoted expansion, splitting it on the characters in
+    /// `$IFS`. Runs of IFS whitespace separate fields and are ignored at the edges,
+    /// while every other IFS character ends a field, even an empty one.
+    fn push_split(&mut self, s: &str) {
+        for c in s.chars() {
+            if !self.ifs.contains(c) {
+                self.push(c.encode_utf8(&mut [0; 4]), false);
+            } else if matches!(c, ' ' | '\t' | '\n') {
+                if self.current.is_some() {
+                    self.end();
+                    self.after_space = true;
+                }
+            } else if self.after_space {
+                self.after_space = false;
+            } else {
+                self.fields.push(self.current.take().unwrap_or_default());
+            }
+        }
+    }
+
+    /// Ends the current field, if any
+    fn end(&mut self) {
+        self.fields.extend(self.current.take());
+        self.after_space = false;
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        self.end();
+        self.fields
+    }
+}
+
+impl Shell {
+    /// Expands the tilde and parameters in a word and removes its quoting
+    pub fn expand_word(&mut self, word: &Word) -> Result<String, ShellError> {
+        let word = self.expand_tilde(word, false);
+        self.join_parts(&word)
+    }
+
+    /// Expands the value of an assignment, where a tilde can also follow a `:`
+    pub fn expand_assignment(&mut self, word: &Word) -> Result<String, ShellError> {
+        let word = self.expand_tilde(word, true);
+        self.join_parts(&word)
+    }
+
+    fn join_parts(&mut self, word: &Word) -> Result<String, ShellError> {
+        let mut res = String::new();
+        for part in &word.parts {
+            self.expand_part(part, &mut res)?;
+        }
+        Ok(res)
+    }
+
+    /// Expands a word into the fields it produces. Braces and tildes are expanded
+    /// first, then parameters and commands, whose unquoted results are split on
+    /// `$IFS`, and finally pathname patterns, while quotes are removed throughout.
+    pub fn expand_fields(&mut self, word: &Word) -> Result<Vec<String>, ShellError> {
+        let mut fields = Fields::new(&self.ifs());
+        for word in brace::expand(word) {
+            // arguments like `PATH=~/bin` are expanded like assignments
+            let word = self.expand_tilde(&word, word.as_assignment().is_some());
+            for part in &word.parts {
+                self.expand_field_part(part, &mut fields)?;
+            }
+            fields.end();
+        }
+
+        let mut res = vec![];
+        for field in fields.finish() {
+            if Pattern::new(&field.pattern).literal().is_some() {
+                res.push(field.text);
+                continue;
+            }
+            let paths = glob::glob(
+                &field.pattern,
+                self.shopts.contains("dotglob"),
+                self.shopts.contains("globstar"),
+            );
+            match paths.is_empty() {
+                false => res.extend(paths),
+                true if self.shopts.contains("failglob") => {
+                    return Err(ShellError::NoMatch(field.text));
+                }
+                true if self.shopts.contains("nullglob") => {}
+                true => res.push(field.text),
+            }
+        }
+        Ok(res)
+    }
+
+    fn expand_field_part(
+        &mut self,
+        part: &WordPart,
+        fields: &mut Fields,
+    ) -> Result<(), ShellError> {
+        match part {
+            WordPart::Literal(s) => fields.push(s, false),
+            WordPart::Quoted(s) => fields.push(s, true),
+            WordPart::DoubleQuoted(parts) => {
+                if parts.is_empty() {
+                    fields.push("", true);
+                }
+                for part in parts {
+                    match part {
+                        // every positional parameter becomes a field of its own
+                        WordPart::Param(param) if is_all_positional(param, &["@"]) => {
+                            for (i, arg) in self.positional.clone().iter().enumerate() {
+                                if i > 0 {
+                                    fields.end();
+                                }
+                                fields.push(arg, true);
+                            }
+                        }
+                        part => {
+                            let mut s = String::new();
+                            self.expand_part(part, &mut s)?;
+                            fields.push(&s, true);
+                        }
+                    }
+                }
+            }
+            WordPart::Param(param) if is_all_positional(param, &["@", "*"]) => {
+                for (i, arg) in self.positional.clone().iter().enumerate() {
+                    if i > 0 {
+                        fields.end();
+                    }
+                    fields.push_split(arg);
+                }
+            }
+            WordPart::Param(param) => fields.push_split(&self.expand_param(param)?),
+            WordPart::Command(src) => fields.push_split(&self.substitute(src)?),
+            WordPart::Arith(word) => fields.push_split(&self.expand_arith(word)?),
+        }
+        Ok(())
+    }
+
+    fn expand_part(&mut self, part: &WordPart, res: &mut String) -> Result<(), ShellError> {
+        match part {
+            WordPart::Literal(s) | WordPart::Quoted(s) => res.push_str(s),
+            WordPart::DoubleQuoted(parts) => {
+                for part in parts {
+                    self.expand_part(part, res)?;
+                }
+            }
+            WordPart::Param(param) => res.push_str(&self.expand_param(param)?),
+            WordPart::Command(src) => res.push_str(&self.substitute(src)?),
+            WordPart::Arith(word) => res.push_str(&self.expand_arith(word)?),
+        }
+        Ok(())
+    }
+
+    /// Expands the parameters and commands in an arithmetic expression and evaluates it
+    fn expand_arith(&mut self, word: &Word) -> Result<String, ShellError> {
+        let expr = self.join_parts(word)?;
+        Ok(arith::eval(&expr, self)?.to_string())
+    }
+
+    /// Runs the commands of a command substitution in a forked copy of the
+    /// shell and returns their output without trailing newlines
+    fn substitute(&mut self, src: &str) -> Result<String, ShellError> {
+        let list = Parser::parse(src, &self.aliases)?;
+        let (mut reader, writer) = pipe()?;
+        let stdout = Output::Pipe(writer);
+
+        let pid = self.fork(|shell| {
+            let r = shell_io::replace_stdout(&stdout)
+                .map_err(ShellError::from)
+                .and_then(|_| shell.run_list(&list));
+            match r {
+                Ok(()) => shell.status_code,
+                Err(e) => {
+                    eprintln!("shell: {e}");
+                    e.status()
+                }
+            }
+        })?;
+        // our copy of the write end has to be closed to see the end of the output
+        drop(stdout);
+
+        let mut out = vec![];
+        let r = reader.read_to_end(&mut out);
+        let status = Shell::wait_pid(pid)?;
+        r?;
+        // like in bash, `$?` sees the status right away, e.g. in `echo $(exit 3) $?`
+        self.status_code = exit_status(status);
+        self.substitution_status = Some(self.status_code);
+
+        let mut res = String::from_utf8_lossy(&out).into_owned();
+        res.truncate(res.trim_end_matches('\n').len());
+        Ok(res)
+    }
+
+    /// Replaces unquoted tilde prefixes with the home directories they name. They
+    /// start the word and, in assignments, also follow the first `=` or any `:`.
+    fn expand_tilde(&self, word: &Word, assignment: bool) -> Word {
+        let mut parts = vec![];
+        let mut at_prefix = true;
+        let mut seen_eq = false;
+
+        for (i, part) in word.parts.iter().enumerate() {
+            let s = match part {
+                WordPart::Literal(s) => s,
+                part => {
+                    parts.push(part.clone());
+                    at_prefix = false;
+                    continue;
+                }
+            };
+
+            let chars = s.chars().collect::<Vec<_>>();
+            let mut literal = String::new();
+            let mut j = 0;
+            while j < chars.len() {
+                if at_prefix && chars[j] == '~' {
+                    let end = chars[j + 1..]
+                        .iter()
+                        .position(|c| *c == '/' || (assignment && *c == ':'))
+                        .map(|k| j + 1 + k);
+                    // the prefix can't continue into a quoted part, as in `~"user"`
+                    let ends_word = end.is_none() && i + 1 == word.parts.len();
+                    let end = end.unwrap_or(chars.len());
+                    let name = chars[j + 1..end].iter().collect::<String>();
+                    if let Some(home) = self
+                        .tilde_prefix(&name)
+                        .filter(|_| end < chars.len() || ends_word)
+                    {
+                        if !literal.is_empty() {
+                            parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                        }
+                        parts.push(WordPart::Quoted(home));
+                        at_prefix = false;
+                        j = end;
+                        continue;
+                    }
+                }
+
+                let c = chars[j];
+                literal.push(c);
+                at_prefix = assignment && (c == ':' || (c == '=' && !seen_eq));
+                seen_eq |= c == '=';
+                j += 1;
+            }
+            if !literal.is_empty() {
+                parts.push(WordPart::Literal(literal));
+            }
+        }
+        Word { parts }
+    }
+
+    /// Returns the directory a tilde prefix like `~`, `~user`, `~+` or `~-` stands for
+    fn tilde_prefix(&self, name: &str) -> Option<String> {
+        match name {
+            "" => self.vars.get("HOME").map(str::to_string).or_else(|| {
+                // SAFETY: getuid can't fail
+                home_dir(unsafe { libc::getpwuid(libc::getuid()) })
+            }),
+            "+" => self.vars.get("PWD").map(str::to_string),
+            "-" => self.vars.get("OLDPWD").map(str::to_string),
+            name => {
+                let name = CString::new(name).ok()?;
+                // SAFETY: the name is a valid C string
+                home_dir(unsafe { libc::getpwnam(name.as_ptr()) })
+            }
+        }
+    }
+
+    /// Expands a word used as a pattern, escaping the quoted parts so that they only
+    /// match themselves, while the results of unquoted expansions stay patterns
+    pub fn expand_pattern(&mut self, word: &Word) -> Result<String, ShellError> {
+        let word = self.expand_tilde(word, false);
+        let mut res = String::new();
+        for part in &word.parts {
+            match part {
+                // a tilde expands to a quoted directory as well
+                WordPart::Quoted(_) | WordPart::DoubleQuoted(_) => {
+                    let mut s = String::new();
+                    self.expand_part(part, &mut s)?;
+                    res.push_str(&glob::escape(&s));
+                }
+                part => self.expand_part(part, &mut res)?,
+            }
+        }
+        Ok(res)
+    }
+
+    fn expand_param(&mut self, param: &Param) -> Result<String, ShellError> {
+        let name = &param.name;
+        if !is_name(name) && !is_special_param(name) {
+            return Err(ShellError::BadSubstitution(format!("${{{name}}}")));
+        }
+
+        let value = self.param(name);
+        // whether the word of a `-`, `=`, `?` or `+` expansion is used
+        let missing = |colon: bool| value.as_deref().is_none_or(|v| colon && v.is_empty());
+        let value_str = value.clone().unwrap_or_default();
+
+        let res = match &param.op {
+            ParamOp::Value => value_str,
+            ParamOp::Length => value_str.chars().count().to_string(),
+            ParamOp::Default { colon, word } => match missing(*colon) {
+                true => self.expand_word(word)?,
+                false => value_str,
+            },
+            ParamOp::Assign { colon, word } => match missing(*colon) {
+                true => {
+                    if !is_name(name) {
+                        let msg = "cannot assign in this way".to_string();
+                        return Err(ShellError::Param(format!("${name}"), msg));
+                    }
+                    let value = self.expand_word(word)?;
+                    self.vars.set(name, value.clone())?;
+                    value
+                }
+                false => value_str,
+            },
+            ParamOp::Error { colon, word } => match missing(*colon) {
+                true => {
+                    let mut msg = self.expand_word(word)?;
+                    if msg.is_empty() {
+                        msg = match colon {
+                            true => "parameter null or not set".to_string(),
+                            false => "parameter not set".to_string(),
+                        };
+                    }
+                    return Err(ShellError::Param(name.to_string(), msg));
+                }
+                false => value_str,
+            },
+            ParamOp::Alternative { colon, word } => match missing(*colon) {
+                true => String::new(),
+                false => self.expand_word(word)?,
+            },
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?);
+                match pattern.match_prefix(&value_str, *longest) {
+                    Some(n) => value_str.chars().skip(n).collect(),
+                    None => value_str,
+                }
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let pattern = Pattern::new(&self.expand_pattern(pattern)?);
+                match pattern.match_suffix(&value_str, *longest) {
+                    Some(n) => {
+                        let keep = value_str.chars().count() - n;
+                        value_str.chars().take(keep).collect()
+                    }
+                    None => value_str,
+                }
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let replacement = self.expand_word(replacement)?;
+                replace(&value_str, &pattern, &replacement, *mode)
+            }
+            ParamOp::Substring { offset, length } => {
+                let offset = self.expand_number(offset)?;
+                let length = match length {
+                    Some(length) => Some(self.expand_number(length)?),
+                    None => None,
+                };
+                substring(&value_str, offset, length).ok_or_else(|| {
+                    ShellError::Param(name.to_string(), "substring expression < 0".into())
+                })?
+            }
+            ParamOp::Case { upper, all } => {
+                let convert = |c: char| -> String {
+                    match upper {
+                        true => c.to_uppercase().collect(),
+                        false => c.to_lowercase().collect(),
+                    }
+                };
+                let mut chars = value_str.chars();
+                match all {
+                    true => chars.map(convert).collect(),
+                    false => match chars.next() {
+                        Some(c) => convert(c) + chars.as_str(),
+                        None => String::new(),
+                    },
+                }
+            }
+        };
+        Ok(res)
+    }
+
+    /// Evaluates a word holding an offset or length of a substring expansion,
+    /// which is an arithmetic expression like `i+1`
+    fn expand_number(&mut self, word: &Word) -> Result<i64, ShellError> {
+        let expr = self.expand_word(word)?;
+        if expr.trim().is_empty() {
+            return Ok(0);
+        }
+        arith::eval(&expr, self)
+    }
+
+    /// Returns the value of a parameter, or `None` if it is unset
+    pub fn param(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status_code.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" => Some(self.positional.join(" ")),
+            // joined with the first character of IFS, or nothing if it is empty
+            "*" => {
+                let sep = self.ifs().chars().next().map(String::from);
+                Some(self.positional.join(&sep.unwrap_or_default()))
+            }
+            _ if name.chars().all(|c| c.is_ascii_digit()) => match name.parse::<usize>() {
+                Ok(0) => Some(self.name.clone()),
+                Err(_) => self.vars.get(name).map(str::to_string),
+                Ok(n) => self.positional.get(n - 1).cloned(),
+            },
+            _ => self.vars.get(name).map(str::to_string),
+        }
+    }
+
+    /// The characters that separate fields, a space, tab and newline when `$IFS` is unset
+    pub fn ifs(&self) -> String {
+        self.vars.get("IFS").unwrap_or(" \t\n").to_string()
+    }
+}
+
+/// Reads the home directory from a password database entry
+fn home_dir(entry: *const libc::passwd) -> Option<String> {
+    if entry.is_null() {
+        return None;
+    }
+    // SAFETY: a non-null entry points to a valid passwd struct with a valid pw_dir
+    let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// Whether a parameter is a plain `$@` or `$*`, as given in `names`
+fn is_all_positional(param: &Param, names: &[&str]) -> bool {
+    param.op == ParamOp::Value && names.contains(&param.name.as_str())
+}
+
+fn is_special_param(name: &str) -> bool {
+    matches!(name, "?" | "$" | "#" | "@" | "*" | "!")
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn replace(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode) -> String {
+    let chars = value.chars().collect::<Vec<_>>();
+    let matcher = Pattern::new(pattern);
+    let collect = |chars: &[char]| chars.iter().collect::<String>();
+
+    match mode {
+        ReplaceMode::Prefix => match matcher.match_prefix(value, true) {
+            Some(n) => replacement.to_string() + &collect(&chars[n..]),
+            None => value.to_string(),
+        },
+        ReplaceMode::Suffix => match matcher.match_suffix(value, true) {
+            Some(n) => collect(&chars[..chars.len() - n]) + replacement,
+            None => value.to_string(),
+        },
+        ReplaceMode::First | ReplaceMode::All => {
+            if pattern.is_empty() {
+                return value.to_string();
+            }
+            let mut res = String::new();
+            let mut pos = 0;
+            while let Some((start, end)) = matcher.find(&chars, pos) {
+                res.push_str(&collect(&chars[pos..start]));
+                res.push_str(replacement);
+                pos = end;
+                // an empty match replaces nothing, so step over the next char
+                if end == start && end < chars.len() {
+                    res.push(chars[end]);
+                    pos += 1;
+                }
+                if mode == ReplaceMode::First || end == chars.len() {
+                    break;
+                }
+            }
+            res.push_str(&collect(&chars[pos.min(chars.len())..]));
+            res
+        }
+    }
+}
+
+/// Takes `length` chars from `offset` on, where a negative offset counts from
+/// the end and a negative length gives the end as an offset from the end
+fn substring(value: &str, offset: i64, length: Option<i64>) -> Option<String> {
+    let chars = value.chars().collect::<Vec<_>>();
+    let n = chars.len() as i64;
+    let start = if offset < 0 { n + offset } else { offset };
+    if start < 0 || start > n {
+        return Some(String::new());
+    }
+    let end = match length {
+        None => n,
+        Some(l) if l < 0 => n + l,
+        Some(l) => (start + l).min(n),
+    };
+    if end < start {
+        return None;
+    }
+    Some(chars[start as usize..end as usize].iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shell that ran `input`
+    fn run(input: &str) -> Shell {
+        let mut shell = Shell::new();
+        shell.execute(input.to_string()).unwrap();
+        shell
+    }
+
+    /// The positional parameters after running `input`, which sets them with `set --`
+    fn fields(input: &str) -> Vec<String> {
+        run(input).positional
+    }
+
+    /// The fields an unquoted expansion of `s` is split into
+    fn split(ifs: &str, s: &str) -> Vec<String> {
+        let mut fields = Fields::new(ifs);
+        fields.push_split(s);
+        fields.finish().into_iter().map(|f| f.text).collect()
+    }
+
+    #[test]
+    fn ifs_whitespace() {
+        assert_eq!(split(" \t\n", " a  b\tc\n"), ["a", "b", "c"]);
+        assert_eq!(split(" ", "   "), [] as [&str; 0]);
+        // whitespace not in IFS is kept
+        assert_eq!(split(":", "a b:c"), ["a b", "c"]);
+    }
+
+    #[test]
+    fn ifs_other_characters() {
+        // each one ends a field, so they can give empty fields, but not at the end
+        assert_eq!(split(":", "a::b:"), ["a", "", "b"]);
+        assert_eq!(split(":", ":a"), ["", "a"]);
+        // whitespace around one of them is part of the same separator
+        assert_eq!(split(": ", " a : b::c "), ["a", "b", "", "c"]);
+    }
+
+    #[test]
+    fn empty_and_unset_ifs() {
+        assert_eq!(fields("IFS=; x='a b:c'; set -- $x"), ["a b:c"]);
+        // an unset IFS splits on whitespace
+        assert_eq!(fields("unset IFS; x=' a  b '; set -- $x"), ["a", "b"]);
+        // unquoted empty results vanish, quoted ones stay
+        assert_eq!(fields("IFS=:; x=a y=; set -- $x$y $y \"\"$y"), ["a", ""]);
+    }
+
+    #[test]
+    fn positional_parameters() {
+        assert_eq!(fields("set --; set -- \"$@\""), [] as [&str; 0]);
+        assert_eq!(fields("set --; set -- \"a$@b\""), ["ab"]);
+        assert_eq!(fields("set -- 'a b' c; set -- \"$@\""), ["a b", "c"]);
+        assert_eq!(fields("set -- 'a b' c; set -- \"x$@y\""), ["xa b", "cy"]);
+        assert_eq!(fields("set -- 'a b' c; set -- $@"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn star_joins_on_the_first_ifs_character() {
+        assert_eq!(fields("set -- 1 2 3; IFS=,:; set -- \"$*\""), ["1,2,3"]);
+        assert_eq!(fields("set -- 1 2 3; IFS=; set -- \"$*\""), ["123"]);
+        assert_eq!(fields("set -- 1 2 3; unset IFS; set -- \"$*\""), ["1 2 3"]);
+    }
+
+    #[test]
+    fn substring_bounds_are_arithmetic() {
+        let shell =
+            run("x=abcdef i=1 n=3; a=${x:1+1} b=${x:i+1:n-1} c=${x:$((1+2))} d=${x:(-3):2}");
+        assert_eq!(shell.vars.get("a"), Some("cdef"));
+        assert_eq!(shell.vars.get("b"), Some("cd"));
+        assert_eq!(shell.vars.get("c"), Some("def"));
+        assert_eq!(shell.vars.get("d"), Some("de"));
+        let shell = run("x=abcdef; a=${x: -2} b=${x:1:-1} c=${x:9}");
+        assert_eq!(shell.vars.get("a"), Some("ef"));
+        assert_eq!(shell.vars.get("b"), Some("bcde"));
+        assert_eq!(shell.vars.get("c"), Some(""));
+    }
+}
@@ -0,0 +1,422 @@
+/// This module contains the shell pattern matching used by
+/// parameter and pathname expansion, with `*`, `?`, bracket
+/// expressions and backslash escapes
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `[...]`
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    /// A named class like `[:alpha:]`
+    Named(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let chars = pattern.chars().collect::<Vec<_>>();
+        let mut tokens = vec![];
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                    continue;
+                }
+                '?' => tokens.push(Token::Any),
+                '*' => {
+                    if tokens.last() != Some(&Token::Star) {
+                        tokens.push(Token::Star);
+                    }
+                }
+                '[' => match parse_class(&chars, i + 1) {
+                    Some((class, end)) => {
+                        tokens.push(class);
+                        i = end;
+                        continue;
+                    }
+                    None => tokens.push(Token::Char('[')),
+                },
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+
+        Pattern { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        self.matches_chars(&text)
+    }
+
+    /// Returns the text the pattern matches if it has no wildcards
+    pub fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|t| match t {
+                Token::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn starts_with_dot(&self) -> bool {
+        self.tokens.first() == Some(&Token::Char('.'))
+    }
+
+    fn matches_chars(&self, text: &[char]) -> bool {
+        let (mut p, mut t) = (0, 0);
+        // where to resume after the last `*` if the rest fails to match
+        let mut backtrack = None;
+
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(Token::Star) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(token) if token.matches(text[t]) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    backtrack = Some((sp, st + 1));
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[p..].iter().all(|t| *t == Token::Star)
+    }
+
+    /// Returns the length in chars of the shortest or longest prefix of `text` that matches
+    pub fn match_prefix(&self, text: &str, longest: bool) -> Option<usize> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut lens = 0..=chars.len();
+        let check = |n: &usize| self.matches_chars(&chars[..*n]);
+        if longest {
+            lens.rfind(check)
+        } else {
+            lens.find(check)
+        }
+    }
+
+    /// Returns the length in chars of the shortest or longest suffix of `text` that matches
+    pub fn match_suffix(&self, text: &str, longest: bool) -> Option<usize> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let n = chars.len();
+        let mut lens = 0..=n;
+        let check = |l: &usize| self.matches_chars(&chars[n - *l..]);
+        if longest {
+            lens.rfind(check)
+        } else {
+            lens.find(check)
+        }
+    }
+
+    /// Finds the first position where the pattern matches, preferring the longest
+    /// match there, and returns its start and end in chars
+    pub fn find(&self, chars: &[char], from: usize) -> Option<(usize, usize)> {
+        (from..=chars.len()).find_map(|start| {
+            (start..=chars.len())
+                .rev()
+                .find(|end| self.matches_chars(&chars[start..*end]))
+                .map(|end| (start, end))
+        })
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(x) => *x == c,
+            Token::Any => true,
+            Token::Star => false,
+            Token::Class { negated, items } => items.iter().any(|i| i.matches(c)) != *negated,
+        }
+    }
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Char(x) => *x == c,
+            ClassItem::Range(a, b) => (*a..=*b).contains(&c),
+            ClassItem::Named(name) => match name.as_str() {
+                "alpha" => c.is_alphabetic(),
+                "digit" => c.is_ascii_digit(),
+                "alnum" => c.is_alphanumeric(),
+                "upper" => c.is_uppercase(),
+                "lower" => c.is_lowercase(),
+                "space" => c.is_whitespace(),
+                "blank" => c == ' ' || c == '\t',
+                "punct" => c.is_ascii_punctuation(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                "cntrl" => c.is_control(),
+                "print" => !c.is_control(),
+                "graph" => !c.is_control() && !c.is_whitespace(),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Parses a bracket expression whose contents start at `start`,
+/// returning it and the index after the closing `]`
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = vec![];
+    let first = i;
+    loop {
+        let c = *chars.get(i)?;
+        match c {
+            // a `]` right after the opening bracket is a literal
+            ']' if i > first => return Some((Token::Class { negated, items }, i + 1)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let rest = chars[i + 2..].iter().collect::<String>();
+                let end = rest.find(":]")?;
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                i += 2 + rest[..end].chars().count() + 2;
+            }
+            '\\' => {
+                items.push(ClassItem::Char(*chars.get(i + 1)?));
+                i += 2;
+            }
+            c if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') => {
+                items.push(ClassItem::Range(c, chars[i + 2]));
+                i += 3;
+            }
+            c => {
+                items.push(ClassItem::Char(c));
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Escapes the pattern characters in `s` so that it only matches itself
+pub fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Expands a pathname pattern into the sorted paths that match it.
+/// Names starting with a dot only match a pattern starting with a dot
+/// unless `dotglob` is set, and with `globstar` a `**` component
+/// matches any number of directories.
+pub fn glob(pattern: &str, dotglob: bool, globstar: bool) -> Vec<String> {
+    let dirs_only = pattern.ends_with('/');
+    let components = pattern
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    let mut paths = vec![match pattern.starts_with('/') {
+        true => "/".to_string(),
+        false => String::new(),
+    }];
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let mut next = vec![];
+
+        for base in &paths {
+            if globstar && *component == "**" {
+                // a trailing `**` matches files too, otherwise only directories
+                if !last {
+                    next.push(base.clone());
+                }
+                walk(base, dotglob, !last || dirs_only, &mut next);
+                continue;
+            }
+
+            let pattern = Pattern::new(component);
+            if let Some(name) = pattern.literal() {
+                let path = join(base, &name);
+                if fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+                continue;
+            }
+
+            for name in read_dir(base) {
+                let hidden = name.starts_with('.') && !dotglob && !pattern.starts_with_dot();
+                if !hidden && pattern.matches(&name) {
+                    let path = join(base, &name);
+                    if last || is_dir(&path) {
+                        next.push(path);
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+
+    if dirs_only {
+        paths.retain(|p| is_dir(p));
+        for p in &mut paths {
+            if !p.ends_with('/') {
+                p.push('/');
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Adds every path below `base`, or only the directories, without following symlinks
+fn walk(base: &str, dotglob: bool, dirs_only: bool, paths: &mut Vec<String>) {
+    for name in read_dir(base) {
+        if name.starts_with('.') && !dotglob {
+            continue;
+        }
+        let path = join(base, &name);
+        let is_real_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+        if is_real_dir || !dirs_only {
+            paths.push(path.clone());
+        }
+        if is_real_dir {
+            walk(&path, dotglob, dirs_only, paths);
+        }
+    }
+}
+
+fn read_dir(dir: &str) -> Vec<String> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+fn join(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        _ if base.ends_with('/') => format!("{base}{name}"),
+        _ => format!("{base}/{name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).matches(text)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*", ""));
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(matches("a*b*c", "abxbc"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("*a", "banana"));
+        assert!(!matches("a*b*c", "abcb"));
+        assert!(matches("*ab*ab", "xabyabab"));
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        // a `]` right after `[` or `[!` is part of the set
+        assert!(matches("[]]", "]"));
+        assert!(matches("[!]a]", "b"));
+        assert!(!matches("[!]a]", "]"));
+        assert!(!matches("[!]a]", "a"));
+        assert!(matches("[^a]", "b"));
+        // an unclosed bracket matches itself
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn named_classes() {
+        assert!(matches("[[:alpha:]]", "Z"));
+        assert!(!matches("[[:alpha:]]", "1"));
+        assert!(matches("[[:digit:][:space:]]", " "));
+        assert!(matches("[![:upper:]]", "a"));
+        assert!(!matches("[![:upper:]]", "A"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches(&escape("a[*]?"), "a[*]?"));
+        assert!(!matches(&escape("a*"), "ab"));
+        assert_eq!(Pattern::new("a\\*b").literal().as_deref(), Some("a*b"));
+        assert_eq!(Pattern::new("a*b").literal(), None);
+    }
+
+    #[test]
+    fn prefixes_and_suffixes() {
+        let pattern = Pattern::new("*/");
+        assert_eq!(pattern.match_prefix("a/b/c", false), Some(2));
+        assert_eq!(pattern.match_prefix("a/b/c", true), Some(4));
+        let pattern = Pattern::new("/*");
+        assert_eq!(pattern.match_suffix("a/b/c", false), Some(2));
+        assert_eq!(pattern.match_suffix("a/b/c", true), Some(4));
+        assert_eq!(Pattern::new("x").match_prefix("abc", false), None);
+    }
+
+    #[test]
+    fn find_prefers_the_longest_match() {
+        let chars = "aXbXXc".chars().collect::<Vec<_>>();
+        assert_eq!(Pattern::new("X*").find(&chars, 0), Some((1, 6)));
+        assert_eq!(Pattern::new("X").find(&chars, 2), Some((3, 4)));
+        assert_eq!(Pattern::new("Y").find(&chars, 0), None);
+    }
+}
@@ -0,0 +1,458 @@
+/// This module contains the job table, which keeps track of the
+/// pipelines running in the background or stopped, and the
+/// `%` job specs that refer to them, along with the terminal
+/// an interactive shell hands to the job in the foreground
+use crate::signals;
+use libc::pid_t;
+use std::io;
+use std::mem::MaybeUninit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    /// Stopped by the given signal
+    Stopped(i32),
+    /// Exited with the given status
+    Exited(i32),
+    /// Killed by the given signal, and whether it dumped core
+    Killed(i32, bool),
+}
+
+impl JobState {
+    /// The status a finished or stopped job ends a command with,
+    /// 128 plus the signal number if a signal stopped or killed it
+    pub fn status(self) -> i32 {
+        match self {
+            JobState::Running => 0,
+            JobState::Exited(status) => status,
+            JobState::Stopped(signo) | JobState::Killed(signo, _) => 128 + signo,
+        }
+    }
+
+    pub fn is_done(self) -> bool {
+        matches!(self, JobState::Exited(_) | JobState::Killed(..))
+    }
+}
+
+/// A process of a job along with what is known about it
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: pid_t,
+    pub state: JobState,
+}
+
+pub struct Job {
+    /// The number `%n` refers to
+    pub id: usize,
+    /// The process group the processes of the job run in
+    pub pgid: pid_t,
+    pub processes: Vec<Process>,
+    /// The commands of the job as they were parsed
+    pub command: String,
+    /// When the job was last started, stopped or continued, which picks the current job
+    touched: u64,
+    /// The terminal modes the job had set when it was stopped
+    pub termios: Option<libc::termios>,
+}
+
+impl Job {
+    /// The state of the job as a whole, which is done when all of its
+    /// processes are, with the status of the last one
+    pub fn state(&self) -> JobState {
+        let mut states = self.processes.iter().map(|p| p.state);
+        if states.clone().any(|s| s == JobState::Running) {
+            return JobState::Running;
+        }
+        match states.clone().find(|s| matches!(s, JobState::Stopped(_))) {
+            Some(stopped) => stopped,
+            None => states.next_back().unwrap_or(JobState::Exited(0)),
+        }
+    }
+
+    /// Describes the state like `jobs` does, e.g. `Running` or `Exit 2`
+    pub fn describe_state(&self) -> String {
+        match self.state() {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped(signo) => signals::describe(signo),
+            JobState::Exited(0) => "Done".to_string(),
+            JobState::Exited(status) => format!("Exit {status}"),
+            JobState::Killed(signo, false) => signals::describe(signo),
+            JobState::Killed(signo, true) => format!("{} (core dumped)", signals::describe(signo)),
+        }
+    }
+
+    /// The command as `jobs` shows it, ending with `&` while it runs in the background
+    pub fn describe_command(&self) -> String {
+        match self.state() {
+            JobState::Running => format!("{} &", self.command),
+            _ => self.command.clone(),
+        }
+    }
+
+    /// Waits for the processes of the job that haven't finished to change state,
+    /// blocking unless `hang` is false. A process that isn't a child of the shell,
+    /// e.g. in a forked copy of it, is left as it is.
+    pub fn update(&mut self, hang: bool) -> io::Result<()> {
+        let flags = match hang {
+            true => libc::WUNTRACED,
+            false => libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED,
+        };
+        for process in &mut self.processes {
+            if process.state.is_done() {
+                continue;
+            }
+            if hang && matches!(process.state, JobState::Stopped(_)) {
+                continue;
+            }
+            let mut status = 0;
+            // SAFETY: waitpid only writes to `status`
+            match unsafe { libc::waitpid(process.pid, &mut status, flags) } {
+                0 => {}
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.raw_os_error() != Some(libc::ECHILD) {
+                        return Err(e);
+                    }
+                }
+                _ => process.state = decode(status),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts a status from waitpid into the state it reports
+pub fn decode(status: i32) -> JobState {
+    if libc::WIFSTOPPED(status) {
+        JobState::Stopped(libc::WSTOPSIG(status))
+    } else if libc::WIFCONTINUED(status) {
+        JobState::Running
+    } else if libc::WIFSIGNALED(status) {
+        JobState::Killed(libc::WTERMSIG(status), libc::WCOREDUMP(status))
+    } else {
+        JobState::Exited(libc::WEXITSTATUS(status))
+    }
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    clock: u64,
+}
+
+impl Jobs {
+    /// Adds a job running the given processes and returns its number,
+    /// one more than the highest number in use
+    pub fn add(&mut self, pgid: pid_t, pids: &[pid_t], command: String) -> usize {
+        let id = self.jobs.last().map_or(1, |job| job.id + 1);
+        self.clock += 1;
+        self.jobs.push(Job {
+            id,
+            pgid,
+            processes: pids
+                .iter()
+                .map(|&pid| Process {
+                    pid,
+                    state: JobState::Running,
+                })
+                .collect(),
+            command,
+            touched: self.clock,
+            termios: None,
+        });
+        id
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let i = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(i))
+    }
+
+    /// The numbers of all jobs, in order
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    /// Marks the job as the most recently used one, which makes it the current job
+    pub fn touch(&mut self, id: usize) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(job) = self.get_mut(id) {
+            job.touched = clock;
+        }
+    }
+
+    /// The jobs that `%+` and `%-` refer to, in that order: the most recently
+    /// stopped ones come first, then the most recently started ones
+    fn by_recency(&self) -> Vec<&Job> {
+        let mut jobs = self.jobs.iter().collect::<Vec<_>>();
+        jobs.sort_by_key(|job| {
+            let stopped = matches!(job.state(), JobState::Stopped(_));
+            std::cmp::Reverse((stopped, job.touched))
+        });
+        jobs
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.by_recency().first().map(|job| job.id)
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        self.by_recency().get(1).map(|job| job.id)
+    }
+
+    /// The `+` or `-` that `jobs` shows for the current and previous job
+    pub fn mark(&self, id: usize) -> char {
+        if self.current() == Some(id) {
+            '+'
+        } else if self.previous() == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// The job a process belongs to
+    pub fn find_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.pgid == pid || job.processes.iter().any(|p| p.pid == pid))
+            .map(|job| job.id)
+    }
+
+    /// Finds the job a spec like `%2`, `%+`, `%-`, `%name` or `%?text` refers to,
+    /// or returns the message explaining why there is none
+    pub fn resolve(&self, spec: &str) -> Result<usize, String> {
+        let no_such_job = || format!("{spec}: no such job");
+        let rest = spec.strip_prefix('%').ok_or_else(no_such_job)?;
+        let found = match rest {
+            "" | "+" | "%" => self.current(),
+            "-" => self.previous(),
+            _ if rest.chars().all(|c| c.is_ascii_digit()) => rest
+                .parse()
+                .ok()
+                .and_then(|id| self.get(id))
+                .map(|job| job.id),
+            _ => {
+                let matches = match rest.strip_prefix('?') {
+                    Some(text) => self.matching(|command| command.contains(text)),
+                    None => self.matching(|command| command.starts_with(rest)),
+                };
+                match matches.as_slice() {
+                    [id] => Some(*id),
+                    [] => None,
+                    _ => return Err(format!("{rest}: ambiguous job spec")),
+                }
+            }
+        };
+        found.ok_or_else(no_such_job)
+    }
+
+    fn matching(&self, f: impl Fn(&str) -> bool) -> Vec<usize> {
+        self.jobs
+            .iter()
+            .filter(|job| f(&job.command))
+            .map(|job| job.id)
+            .collect()
+    }
+
+    /// Marks the stopped processes that `kill(target, SIGCONT)` reached as running
+    /// again, all those of a job for the negative of its process group
+    pub fn continued(&mut self, target: pid_t) {
+        for job in &mut self.jobs {
+            for process in &mut job.processes {
+                let reached = match target < 0 {
+                    true => job.pgid == -target,
+                    false => process.pid == target,
+                };
+                if reached && matches!(process.state, JobState::Stopped(_)) {
+                    process.state = JobState::Running;
+                }
+            }
+        }
+    }
+
+    /// Records the status waitpid returned for a process of a job
+    pub fn record(&mut self, pid: pid_t, status: i32) {
+        for job in &mut self.jobs {
+            if let Some(process) = job.processes.iter_mut().find(|p| p.pid == pid) {
+                process.state = decode(status);
+            }
+        }
+    }
+
+    /// Checks on every job without waiting
+    pub fn update(&mut self) {
+        for job in &mut self.jobs {
+            let _ = job.update(false);
+        }
+    }
+
+    /// Formats a line about a job like `jobs` does, with its process group when `long`
+    pub fn format(&self, job: &Job, long: bool) -> String {
+        let mark = self.mark(job.id);
+        let state = job.describe_state();
+        let command = job.describe_command();
+        match long {
+            true => format!("[{}]{mark} {} {state:<24}{command}", job.id, job.pgid),
+            false => format!("[{}]{mark}  {state:<24}{command}", job.id),
+        }
+    }
+}
+
+/// The terminal of an interactive shell, which it hands to the job in
+/// the foreground and takes back along with its modes afterwards
+pub struct Terminal {
+    /// The process group of the shell
+    pgid: pid_t,
+    /// The modes of the terminal while the shell reads commands
+    termios: libc::termios,
+}
+
+impl Terminal {
+    /// Puts the shell into a process group of its own in the foreground of the
+    /// terminal on stdin, ignoring the signals sent from the keyboard. Returns
+    /// `None` when stdin is not a terminal, so there is no job control.
+    pub fn take() -> Option<Terminal> {
+        let termios = Terminal::modes()?;
+        // SAFETY: these calls only change the process group and signal dispositions
+        unsafe {
+            // wait until started in the foreground, as a shell started
+            // in the background would otherwise steal the terminal
+            while libc::tcgetpgrp(0) != libc::getpgrp() {
+                libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+            }
+            signals::ignore_job_signals();
+            // fails harmlessly when the shell already leads its session
+            libc::setpgid(0, 0);
+            let pgid = libc::getpgrp();
+            libc::tcsetpgrp(0, pgid);
+            Some(Terminal { pgid, termios })
+        }
+    }
+
+    /// The current modes of the terminal
+    pub fn modes() -> Option<libc::termios> {
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: tcgetattr fills in `termios` when it succeeds
+        unsafe {
+            match libc::tcgetattr(0, termios.as_mut_ptr()) {
+                0 => Some(termios.assume_init()),
+                _ => None,
+            }
+        }
+    }
+
+    /// Puts a job in the foreground, with the modes it had when it was stopped
+    pub fn give(&self, pgid: pid_t, termios: Option<&libc::termios>) {
+        // SAFETY: these calls only change the state of the terminal
+        unsafe {
+            if let Some(termios) = termios {
+                libc::tcsetattr(0, libc::TCSADRAIN, termios);
+            }
+            libc::tcsetpgrp(0, pgid);
+        }
+    }
+
+    /// Puts the shell back in the foreground and restores its modes
+    pub fn reclaim(&self) {
+        // SAFETY: these calls only change the state of the terminal
+        unsafe {
+            libc::tcsetpgrp(0, self.pgid);
+            libc::tcsetattr(0, libc::TCSADRAIN, &self.termios);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table holding jobs running `commands`, numbered from 1 and started
+    /// in that order, with made up process ids
+    fn jobs(commands: &[&str]) -> Jobs {
+        let mut jobs = Jobs::default();
+        for (i, command) in commands.iter().enumerate() {
+            let pid = 1000 + i as pid_t;
+            jobs.add(pid, &[pid], command.to_string());
+        }
+        jobs
+    }
+
+    #[test]
+    fn numbers() {
+        let mut jobs = jobs(&["sleep 1", "sleep 2", "sleep 3"]);
+        assert_eq!(jobs.resolve("%1"), Ok(1));
+        assert_eq!(jobs.resolve("%3"), Ok(3));
+        assert_eq!(jobs.resolve("%4"), Err("%4: no such job".to_string()));
+        jobs.remove(2);
+        assert_eq!(jobs.resolve("%2"), Err("%2: no such job".to_string()));
+        // a new job gets one more than the highest number in use
+        assert_eq!(jobs.add(2000, &[2000], "true".to_string()), 4);
+    }
+
+    #[test]
+    fn current_and_previous() {
+        let mut jobs = jobs(&["a", "b", "c"]);
+        for spec in ["%", "%+", "%%"] {
+            assert_eq!(jobs.resolve(spec), Ok(3));
+        }
+        assert_eq!(jobs.resolve("%-"), Ok(2));
+
+        // a stopped job comes before running ones, even older ones
+        jobs.get_mut(1).unwrap().processes[0].state = JobState::Stopped(libc::SIGTSTP);
+        assert_eq!(jobs.resolve("%+"), Ok(1));
+        assert_eq!(jobs.resolve("%-"), Ok(3));
+
+        // continuing a job makes it the most recently used one
+        jobs.get_mut(1).unwrap().processes[0].state = JobState::Running;
+        jobs.touch(2);
+        assert_eq!(jobs.resolve("%+"), Ok(2));
+        assert_eq!(jobs.resolve("%-"), Ok(3));
+    }
+
+    #[test]
+    fn with_one_job_there_is_no_previous_one() {
+        let jobs = jobs(&["a"]);
+        assert_eq!(jobs.resolve("%+"), Ok(1));
+        assert_eq!(jobs.resolve("%-"), Err("%-: no such job".to_string()));
+        let jobs = Jobs::default();
+        assert_eq!(jobs.resolve("%%"), Err("%%: no such job".to_string()));
+    }
+
+    #[test]
+    fn commands() {
+        let jobs = jobs(&["sleep 10", "vim notes", "sleep 20 | cat"]);
+        assert_eq!(jobs.resolve("%vim"), Ok(2));
+        assert_eq!(jobs.resolve("%?notes"), Ok(2));
+        assert_eq!(jobs.resolve("%?cat"), Ok(3));
+        // prefixes and substrings have to pick one job
+        assert_eq!(
+            jobs.resolve("%sleep"),
+            Err("sleep: ambiguous job spec".to_string())
+        );
+        assert_eq!(
+            jobs.resolve("%?sleep"),
+            Err("?sleep: ambiguous job spec".to_string())
+        );
+        assert_eq!(
+            jobs.resolve("%emacs"),
+            Err("%emacs: no such job".to_string())
+        );
+        assert_eq!(jobs.resolve("%?x"), Err("%?x: no such job".to_string()));
+    }
+
+    #[test]
+    fn specs_start_with_percent() {
+        let jobs = jobs(&["a"]);
+        assert_eq!(jobs.resolve("1"), Err("1: no such job".to_string()));
+    }
+}
@@ -0,0 +1,869 @@
+/// This module contains the lexer which splits an input line
+/// into words and operators for the parser
+use crate::ast::{Param, ParamOp, ReplaceMode, Word, WordPart};
+use crate::parser::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    /// A number directly in front of a redirection operator, e.g. the `2` in `2>`
+    IoNumber(u32),
+    Op(Op),
+    /// The expression of a `((...))` command
+    Arith(Word),
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Pipe,
+    OrIf,
+    Amp,
+    AndIf,
+    Semi,
+    DSemi,
+    SemiAnd,
+    DSemiAnd,
+    LParen,
+    RParen,
+    Less,
+    Great,
+    DGreat,
+    Clobber,
+    DLess,
+    DLessDash,
+    TLess,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    AndGreat,
+    AndDGreat,
+}
+
+/// Operators ordered so that the longest match is tried first
+const OPERATORS: &[(&str, Op)] = &[
+    ("<<<", Op::TLess),
+    (";;&", Op::DSemiAnd),
+    ("<<-", Op::DLessDash),
+    ("&>>", Op::AndDGreat),
+    ("||", Op::OrIf),
+    ("&&", Op::AndIf),
+    (";;", Op::DSemi),
+    (";&", Op::SemiAnd),
+    (">>", Op::DGreat),
+    (">|", Op::Clobber),
+    ("<<", Op::DLess),
+    ("<&", Op::LessAnd),
+    (">&", Op::GreatAnd),
+    ("<>", Op::LessGreat),
+    ("&>", Op::AndGreat),
+    ("|", Op::Pipe),
+    ("&", Op::Amp),
+    (";", Op::Semi),
+    ("(", Op::LParen),
+    (")", Op::RParen),
+    ("<", Op::Less),
+    (">", Op::Great),
+];
+
+impl Op {
+    pub fn is_redirect(&self) -> bool {
+        matches!(
+            self,
+            Op::Less
+                | Op::Great
+                | Op::DGreat
+                | Op::Clobber
+                | Op::DLess
+                | Op::DLessDash
+                | Op::TLess
+                | Op::LessAnd
+                | Op::GreatAnd
+                | Op::LessGreat
+                | Op::AndGreat
+                | Op::AndDGreat
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        OPERATORS
+            .iter()
+            .find(|(_, op)| op == self)
+            .map(|(s, _)| *s)
+            .unwrap_or_default()
+    }
+}
+
+impl Token {
+    /// Returns how the token looked in the input, for error messages
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Word(w) => w.unquoted(),
+            Token::IoNumber(n) => n.to_string(),
+            Token::Op(op) => op.as_str().to_string(),
+            Token::Arith(_) => "((".to_string(),
+            Token::Newline => "newline".to_string(),
+        }
+    }
+}
+
+/// A here-document whose delimiter has been read but whose body
+/// starts only after the next newline
+struct PendingHereDoc {
+    /// Index of the delimiter word in the token list
+    token: usize,
+    strip_tabs: bool,
+}
+
+pub struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    heredocs: Vec<PendingHereDoc>,
+}
+
+impl Lexer {
+    pub fn new(input: &str) -> Lexer {
+        Lexer {
+            chars: input.chars().collect(),
+            pos: 0,
+            heredocs: vec![],
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        self.tokens(false)
+    }
+
+    /// Splits the input into tokens. When `nested` is true, stops right after
+    /// the unmatched `)` that ends a command substitution.
+    fn tokens(&mut self, nested: bool) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = vec![];
+        let mut heredoc_op = None;
+        let mut depth = 0;
+        // the number of `case` commands that are still open
+        let mut cases = 0;
+
+        loop {
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None if nested => return Err(ParseError::Incomplete(")".into())),
+                None => break,
+            };
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '\n' => {
+                    self.pos += 1;
+                    tokens.push(Token::Newline);
+                    self.read_heredocs(&mut tokens)?;
+                }
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                '\\' if self.peek(1) == Some('\n') => self.pos += 2,
+                _ => {
+                    if let Some(word) = self.arith_command()? {
+                        heredoc_op = None;
+                        tokens.push(Token::Arith(word));
+                    } else if let Some(op) = self.operator() {
+                        match op {
+                            Op::LParen => depth += 1,
+                            // the `)` after a pattern of an open `case` doesn't end the substitution
+                            Op::RParen if nested && depth == 0 && cases == 0 => break,
+                            Op::RParen if depth > 0 => depth -= 1,
+                            _ => {}
+                        }
+                        heredoc_op = match op {
+                            Op::DLess => Some(false),
+                            Op::DLessDash => Some(true),
+                            _ => None,
+                        };
+                        tokens.push(Token::Op(op));
+                    } else {
+                        let word = self.word()?;
+                        if let Token::Word(w) = &word {
+                            match w.keyword() {
+                                Some("case") if at_command(&tokens) => cases += 1,
+                                Some("esac") if cases > 0 && at_command(&tokens) => cases -= 1,
+                                _ => {}
+                            }
+                        }
+                        if let (Some(strip_tabs), Token::Word(_)) = (heredoc_op.take(), &word) {
+                            self.heredocs.push(PendingHereDoc {
+                                token: tokens.len(),
+                                strip_tabs,
+                            });
+                        }
+                        tokens.push(word);
+                    }
+                }
+            }
+        }
+
+        if !self.heredocs.is_empty() {
+            return Err(ParseError::Incomplete("here-document".into()));
+        }
+
+        Ok(tokens)
+    }
+
+    /// Reads the bodies of all here-documents started on the line that just ended
+    /// and replaces their delimiter words with the bodies
+    fn read_heredocs(&mut self, tokens: &mut [Token]) -> Result<(), ParseError> {
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            let delimiter = match &tokens[heredoc.token] {
+                Token::Word(w) => w,
+                _ => continue,
+            };
+            let quoted = delimiter
+                .parts
+                .iter()
+                .any(|p| !matches!(p, WordPart::Literal(_)));
+            let delimiter = delimiter.unquoted();
+
+            let mut body = String::new();
+            loop {
+                if self.pos >= self.chars.len() {
+                    return Err(ParseError::Incomplete("here-document".into()));
+                }
+                let start = self.pos;
+                while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                let mut line: String = self.chars[start..self.pos].iter().collect();
+                self.pos += 1;
+
+                if heredoc.strip_tabs {
+                    line = line.trim_start_matches('\t').to_string();
+                }
+                if line == delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+
+            let part = if quoted {
+                WordPart::Quoted(body)
+            } else {
+                WordPart::DoubleQuoted(Lexer::new(&body).quoted_parts(None)?)
+            };
+            tokens[heredoc.token] = Token::Word(Word { parts: vec![part] });
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek(i) == Some(c))
+    }
+
+    /// Consumes the operator at the current position, if there is one
+    fn operator(&mut self) -> Option<Op> {
+        let (s, op) = OPERATORS.iter().find(|(s, _)| self.starts_with(s))?;
+        self.pos += s.chars().count();
+        Some(*op)
+    }
+
+    fn at_word_end(&self) -> bool {
+        match self.peek(0) {
+            None | Some(' ' | '\t' | '\n') => true,
+            Some(_) => OPERATORS.iter().any(|(s, _)| self.starts_with(s)),
+        }
+    }
+
+    fn word(&mut self) -> Result<Token, ParseError> {
+        let parts = self.word_parts(None)?;
+
+        let is_io_number = matches!(parts.as_slice(), [WordPart::Literal(s)]
+                if s.chars().all(|c| c.is_ascii_digit()))
+            && matches!(self.peek(0), Some('<' | '>'));
+        if is_io_number {
+            if let Some(WordPart::Literal(s)) = parts.first() {
+                if let Ok(n) = s.parse() {
+                    return Ok(Token::IoNumber(n));
+                }
+            }
+        }
+
+        Ok(Token::Word(Word { parts }))
+    }
+
+    /// Reads the parts of a word up to its end, or inside `${...}` or `$((...))`
+    /// up to the first unquoted character in `stops` that is not inside
+    /// parentheses, where blanks and operators don't end the word. `closer`
+    /// is what the input is missing if it ends first.
+    fn word_parts(&mut self, until: Option<(&[char], &str)>) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut depth = 0;
+
+        loop {
+            let c = match (self.peek(0), until) {
+                (None, Some((_, closer))) => return Err(ParseError::Incomplete(closer.into())),
+                (Some(')'), Some(_)) if depth > 0 => {
+                    depth -= 1;
+                    ')'
+                }
+                (Some(c), Some((stops, _))) if stops.contains(&c) => break,
+                (Some(c), Some(_)) => {
+                    if c == '(' {
+                        depth += 1;
+                    }
+                    c
+                }
+                (_, None) if self.at_word_end() => break,
+                (_, None) => self.chars[self.pos],
+            };
+            self.pos += 1;
+            match c {
+                '\\' => match self.peek(0) {
+                    Some('\n') => self.pos += 1,
+                    Some(c) => {
+                        self.pos += 1;
+                        flush(&mut literal, &mut parts);
+                        parts.push(WordPart::Quoted(c.to_string()));
+                    }
+                    None => return Err(ParseError::Incomplete("\\".into())),
+                },
+                '\'' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::Quoted(self.single_quoted()?));
+                }
+                '"' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.quoted_parts(Some('"'))?));
+                }
+                '$' => match self.dollar()? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                '`' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::Command(self.backquoted(false)?));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        flush(&mut literal, &mut parts);
+        Ok(parts)
+    }
+
+    fn word_until(&mut self, stops: &[char], closer: &str) -> Result<Word, ParseError> {
+        Ok(Word {
+            parts: self.word_parts(Some((stops, closer)))?,
+        })
+    }
+
+    /// Reads a `((...))` arithmetic command if one starts here
+    fn arith_command(&mut self) -> Result<Option<Word>, ParseError> {
+        if !self.starts_with("((") {
+            return Ok(None);
+        }
+        let start = self.pos;
+        self.pos += 2;
+        let word = self.arith()?;
+        if word.is_none() {
+            self.pos = start;
+        }
+        Ok(word)
+    }
+
+    /// Reads an arithmetic expression after its opening `((` up to and
+    /// including the closing `))`, or returns `None` if the parentheses
+    /// don't close that way, as in `$( (cmd) )`
+    fn arith(&mut self) -> Result<Option<Word>, ParseError> {
+        let word = self.word_until(&[')'], "))")?;
+        if self.starts_with("))") {
+            self.pos += 2;
+            return Ok(Some(word));
+        }
+        Ok(None)
+    }
+
+    /// Reads the expansion following a `$`, or returns `None`
+    /// when the `$` is just a literal dollar sign
+    fn dollar(&mut self) -> Result<Option<WordPart>, ParseError> {
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let name = match c {
+            '(' => {
+                self.pos += 1;
+                if self.peek(0) == Some('(') {
+                    let start = self.pos;
+                    self.pos += 1;
+                    if let Some(word) = self.arith()? {
+                        return Ok(Some(WordPart::Arith(word)));
+                    }
+                    self.pos = start;
+                }
+                return self.command_sub().map(|src| Some(WordPart::Command(src)));
+            }
+            '{' => {
+                self.pos += 1;
+                let start = self.pos;
+                let param = match self.braced_param()? {
+                    Some(param) => param,
+                    // keep the malformed text so that expanding it reports a bad substitution
+                    None => {
+                        self.pos = start;
+                        Param {
+                            name: self.skip_braces()?,
+                            op: ParamOp::Value,
+                        }
+                    }
+                };
+                return Ok(Some(WordPart::Param(param)));
+            }
+            '?' | '$' | '#' | '@' | '*' | '!' | '0'..='9' => {
+                self.pos += 1;
+                c.to_string()
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => self.name(),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(WordPart::Param(Param {
+            name,
+            op: ParamOp::Value,
+        })))
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek(0)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Reads the name of a parameter inside `${...}`
+    fn param_name(&mut self) -> String {
+        match self.peek(0) {
+            Some(c @ ('?' | '$' | '#' | '@' | '*' | '!' | '-')) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                self.chars[start..self.pos].iter().collect()
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.name(),
+            _ => String::new(),
+        }
+    }
+
+    /// Parses the inside of `${...}` up to and including the closing brace,
+    /// or returns `None` if it is not a valid expansion
+    fn braced_param(&mut self) -> Result<Option<Param>, ParseError> {
+        if self.peek(0) == Some('#') && self.peek(1) != Some('}') {
+            self.pos += 1;
+            let name = self.param_name();
+            if name.is_empty() || self.peek(0) != Some('}') {
+                return Ok(None);
+            }
+            self.pos += 1;
+            return Ok(Some(Param {
+                name,
+                op: ParamOp::Length,
+            }));
+        }
+
+        let name = self.param_name();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Err(ParseError::Incomplete("}".into())),
+        };
+        self.pos += 1;
+
+        let op = match c {
+            '}' => {
+                return Ok(Some(Param {
+                    name,
+                    op: ParamOp::Value,
+                }));
+            }
+            ':' => match self.peek(0) {
+                Some(c @ ('-' | '=' | '?' | '+')) => {
+                    self.pos += 1;
+                    let word = self.word_until(&['}'], "}")?;
+                    word_op(c, true, word)
+                }
+                _ => {
+                    let offset = self.word_until(&[':', '}'], "}")?;
+                    let length = if self.peek(0) == Some(':') {
+                        self.pos += 1;
+                        Some(self.word_until(&['}'], "}")?)
+                    } else {
+                        None
+                    };
+                    ParamOp::Substring { offset, length }
+                }
+            },
+            '-' | '=' | '?' | '+' => word_op(c, false, self.word_until(&['}'], "}")?),
+            '#' | '%' => {
+                let longest = self.peek(0) == Some(c);
+                if longest {
+                    self.pos += 1;
+                }
+                let pattern = self.word_until(&['}'], "}")?;
+                if c == '#' {
+                    ParamOp::RemovePrefix { longest, pattern }
+                } else {
+                    ParamOp::RemoveSuffix { longest, pattern }
+                }
+            }
+            '/' => {
+                let mode = match self.peek(0) {
+                    Some('/') => ReplaceMode::All,
+                    Some('#') => ReplaceMode::Prefix,
+                    Some('%') => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                if mode != ReplaceMode::First {
+                    self.pos += 1;
+                }
+                let pattern = self.word_until(&['/', '}'], "}")?;
+                let replacement = if self.peek(0) == Some('/') {
+                    self.pos += 1;
+                    self.word_until(&['}'], "}")?
+                } else {
+                    Word::default()
+                };
+                ParamOp::Replace {
+                    mode,
+                    pattern,
+                    replacement,
+                }
+            }
+            '^' | ',' => {
+                let all = self.peek(0) == Some(c);
+                if all {
+                    self.pos += 1;
+                }
+                ParamOp::Case {
+                    upper: c == '^',
+                    all,
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        match self.peek(0) {
+            Some('}') => {
+                self.pos += 1;
+                Ok(Some(Param { name, op }))
+            }
+            Some(_) => Ok(None),
+            None => Err(ParseError::Incomplete("}".into())),
+        }
+    }
+
+    /// Reads the commands of a `$(...)` up to the closing parenthesis, returning their source
+    fn command_sub(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        // here-documents of the enclosing line are read after it ends, not in here
+        let outer = std::mem::take(&mut self.heredocs);
+        let r = self.tokens(true);
+        self.heredocs = outer;
+        r?;
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    /// Reads an old style `` `...` `` command substitution after its opening backquote.
+    /// A backslash only escapes `$`, `` ` ``, `\` and, inside double quotes, `"`.
+    fn backquoted(&mut self, in_double_quotes: bool) -> Result<String, ParseError> {
+        let mut src = String::new();
+        loop {
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None => return Err(ParseError::Incomplete("`".into())),
+            };
+            self.pos += 1;
+            match c {
+                '`' => return Ok(src),
+                '\\' => match self.peek(0) {
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.pos += 1;
+                        src.push(c);
+                    }
+                    Some('"') if in_double_quotes => {
+                        self.pos += 1;
+                        src.push('"');
+                    }
+                    _ => src.push('\\'),
+                },
+                c => src.push(c),
+            }
+        }
+    }
+
+    /// Skips to the brace closing a `${`, returning the text in between
+    fn skip_braces(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.peek(0) {
+                Some('{') => depth += 1,
+                Some('}') => depth -= 1,
+                Some('\\') => self.pos += 1,
+                Some(_) => {}
+                None => return Err(ParseError::Incomplete("}".into())),
+            }
+            self.pos += 1;
+        }
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    fn single_quoted(&mut self) -> Result<String, ParseError> {
+        let mut res = String::new();
+        loop {
+            match self.peek(0) {
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(res);
+                }
+                Some(c) => {
+                    self.pos += 1;
+                    res.push(c);
+                }
+                None => return Err(ParseError::Incomplete("'".into())),
+            }
+        }
+    }
+
+    /// Reads the inside of double quotes up to the closing `end` quote,
+    /// or the body of a here-document up to the end of input when `end` is `None`
+    fn quoted_parts(&mut self, end: Option<char>) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        loop {
+            let c = match (self.peek(0), end) {
+                (Some(c), _) => c,
+                (None, Some(end)) => return Err(ParseError::Incomplete(end.to_string())),
+                (None, None) => break,
+            };
+            self.pos += 1;
+            match c {
+                c if Some(c) == end => break,
+                '\\' => match self.peek(0) {
+                    Some('\n') => self.pos += 1,
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.pos += 1;
+                        literal.push(c);
+                    }
+                    Some(c) if Some(c) == end => {
+                        self.pos += 1;
+                        literal.push(c);
+                    }
+                    _ => literal.push('\\'),
+                },
+                '$' => match self.dollar()? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                '`' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::Command(self.backquoted(end.is_some())?));
+                }
+                c => literal.push(c),
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(parts)
+    }
+}
+
+/// Whether the next token would be the first word of a command, as far as
+/// the lexer can tell
+fn at_command(tokens: &[Token]) -> bool {
+    match tokens.last() {
+        None | Some(Token::Newline) => true,
+        Some(Token::Op(op)) => !op.is_redirect(),
+        Some(Token::Word(w)) => matches!(
+            w.keyword(),
+            Some("!" | "do" | "else" | "elif" | "if" | "then" | "until" | "while" | "{")
+        ),
+        _ => false,
+    }
+}
+
+fn word_op(c: char, colon: bool, word: Word) -> ParamOp {
+    match c {
+        '-' => ParamOp::Default { colon, word },
+        '=' => ParamOp::Assign { colon, word },
+        '?' => ParamOp::Error { colon, word },
+        _ => ParamOp::Alternative { colon, word },
+    }
+}
+
+fn flush(literal: &mut String, parts: &mut Vec<WordPart>) {
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        Lexer::new(input).tokenize().unwrap()
+    }
+
+    fn word(parts: Vec<WordPart>) -> Token {
+        Token::Word(Word { parts })
+    }
+
+    fn literal(s: &str) -> Token {
+        word(vec![WordPart::Literal(s.to_string())])
+    }
+
+    /// The part holding the body of the one here-document in `input`
+    fn heredoc(input: &str) -> WordPart {
+        match tokens(input).as_slice() {
+            [_, Token::Op(Op::DLess | Op::DLessDash), Token::Word(w), Token::Newline] => {
+                w.parts[0].clone()
+            }
+            t => panic!("{input:?}: expected a here-document, got {t:?}"),
+        }
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            tokens("a|b||c&&d;e&"),
+            [
+                literal("a"),
+                Token::Op(Op::Pipe),
+                literal("b"),
+                Token::Op(Op::OrIf),
+                literal("c"),
+                Token::Op(Op::AndIf),
+                literal("d"),
+                Token::Op(Op::Semi),
+                literal("e"),
+                Token::Op(Op::Amp),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_operators_are_literal() {
+        let quoted = |s: &str| WordPart::Quoted(s.to_string());
+        assert_eq!(
+            tokens(r#"echo 'a|b' "c;d" e\&f"#),
+            [
+                literal("echo"),
+                word(vec![quoted("a|b")]),
+                word(vec![WordPart::DoubleQuoted(vec![WordPart::Literal(
+                    "c;d".to_string()
+                )])]),
+                word(vec![
+                    WordPart::Literal("e".to_string()),
+                    quoted("&"),
+                    WordPart::Literal("f".to_string()),
+                ]),
+            ]
+        );
+        // a comment only starts a word
+        assert_eq!(tokens("a#b # c"), [literal("a#b")]);
+    }
+
+    #[test]
+    fn io_numbers() {
+        assert_eq!(
+            tokens("cmd 2>err 2>&1 a2>x 2 >y"),
+            [
+                literal("cmd"),
+                Token::IoNumber(2),
+                Token::Op(Op::Great),
+                literal("err"),
+                Token::IoNumber(2),
+                Token::Op(Op::GreatAnd),
+                literal("1"),
+                literal("a2"),
+                Token::Op(Op::Great),
+                literal("x"),
+                literal("2"),
+                Token::Op(Op::Great),
+                literal("y"),
+            ]
+        );
+        assert_eq!(
+            tokens("cat 0<in 10>>out"),
+            [
+                literal("cat"),
+                Token::IoNumber(0),
+                Token::Op(Op::Less),
+                literal("in"),
+                Token::IoNumber(10),
+                Token::Op(Op::DGreat),
+                literal("out"),
+            ]
+        );
+    }
+
+    #[test]
+    fn heredocs() {
+        // an unquoted delimiter expands the body like double quotes
+        let param = WordPart::Param(Param {
+            name: "x".to_string(),
+            op: ParamOp::Value,
+        });
+        assert_eq!(
+            heredoc("cat <<EOF\n$x\nEOF\n"),
+            WordPart::DoubleQuoted(vec![param, WordPart::Literal("\n".to_string())])
+        );
+        // any quoting in the delimiter keeps the body as it is
+        for input in [
+            "cat <<'EOF'\n$x\nEOF\n",
+            "cat <<\"EOF\"\n$x\nEOF\n",
+            "cat <<E\\OF\n$x\nEOF\n",
+        ] {
+            assert_eq!(heredoc(input), WordPart::Quoted("$x\n".to_string()));
+        }
+        // `<<-` strips leading tabs, also from the delimiter line
+        assert_eq!(
+            heredoc("cat <<-'EOF'\n\t\ta\n\tEOF\n"),
+            WordPart::Quoted("a\n".to_string())
+        );
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let incomplete = |input: &str| match Lexer::new(input).tokenize() {
+            Err(ParseError::Incomplete(s)) => s,
+            r => panic!("{input:?}: expected incomplete input, got {r:?}"),
+        };
+        assert_eq!(incomplete("echo 'a"), "'");
+        assert_eq!(incomplete("echo \"a"), "\"");
+        assert_eq!(incomplete("echo $(a"), ")");
+        assert_eq!(incomplete("echo ${a"), "}");
+        assert_eq!(incomplete("cat <<EOF\na\n"), "here-document");
+        assert!(Lexer::new("cat <<EOF\na\nEOF\n").tokenize().is_ok());
+    }
+}
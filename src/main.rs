@@ -1,37 +1,305 @@
+mod arith;
+mod ast;
 mod autocomplete;
+mod brace;
 mod builtins;
 mod command;
+mod error;
+mod expand;
+mod glob;
+mod jobs;
+mod lexer;
+mod parser;
 mod shell;
 mod shell_io;
+mod signals;
+mod traps;
 mod trie;
+mod vars;
 
 use crate::autocomplete::ShellAutocomplete;
+use crate::error::ShellError;
+use crate::jobs::Terminal;
+use crate::lexer::{Lexer, Token};
+use crate::parser::{ParseError, Parser};
 use crate::shell::Shell;
+use crate::shell_io::StdinLines;
 use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
-use std::io::{self};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 
-fn main() -> io::Result<()> {
+/// Function calls recurse through the executor, so nested calls
+/// need more room than the main thread's stack offers
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// How the shell was started, from its command line
+#[derive(Default)]
+struct Options {
+    /// `-c`, run the first argument as commands
+    command: bool,
+    /// `-s`, read commands from stdin even when there are arguments
+    stdin: bool,
+    /// `-l`, run the login profiles as well, also set by a `-` before the name
+    /// the shell was started as
+    login: bool,
+    /// `--norc`, don't run the rc file of an interactive shell
+    norc: bool,
+    /// `--noprofile`, don't run the profiles of a login shell
+    noprofile: bool,
+    /// `--rcfile FILE`, the rc file to run instead of the usual one
+    rcfile: Option<String>,
+    /// `--posix`, run the file named by `$ENV` instead of the rc file
+    posix: bool,
+    /// The arguments after the options
+    args: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => break,
+                // a lone `-` ends the options like `--`
+                "-" => break,
+                "--norc" => options.norc = true,
+                "--noprofile" => options.noprofile = true,
+                "--posix" => options.posix = true,
+                "--login" => options.login = true,
+                "--rcfile" => match args.next() {
+                    Some(file) => options.rcfile = Some(file),
+                    None => return Err(format!("{arg}: option requires an argument")),
+                },
+                _ if arg.starts_with("--") => return Err(format!("{arg}: invalid option")),
+                _ if arg.starts_with('-') => {
+                    for c in arg[1..].chars() {
+                        match c {
+                            'c' => options.command = true,
+                            's' => options.stdin = true,
+                            'l' => options.login = true,
+                            _ => return Err(format!("-{c}: invalid option")),
+                        }
+                    }
+                }
+                _ => {
+                    options.args.push(arg);
+                    break;
+                }
+            }
+        }
+        options.args.extend(args);
+        Ok(options)
+    }
+}
+
+fn main() {
+    let status = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .map(|shell| {
+            // from here on this thread only waits, which `Shell::fork` relies on
+            signals::block_all();
+            shell
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("shell: {e}");
+            1
+        });
+    std::process::exit(status);
+}
+
+/// Runs the shell and returns the status it exits with
+fn run() -> i32 {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("shell: {e}");
+            return 2;
+        }
+    };
     let mut shell = Shell::new();
-    let autocomplete = ShellAutocomplete::new(&shell.path);
+    let login = options.login || shell.name.starts_with('-');
+    if login && !options.noprofile {
+        run_profiles(&mut shell);
+    }
+    let mut args = options.args.into_iter();
+
+    if options.command {
+        let Some(command) = args.next() else {
+            eprintln!("shell: -c: option requires an argument");
+            return 2;
+        };
+        // `-c 'commands' name args...` sets `$0` to the name
+        if let Some(name) = args.next() {
+            shell.name = name;
+        }
+        shell.positional = args.collect();
+        let status = shell.run_lines("-c", command.lines().map(String::from));
+        return shell.run_exit_trap(status);
+    }
+
+    if !options.stdin {
+        if let Some(script) = args.next() {
+            let text = match std::fs::read(&script) {
+                Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+                Err(e) => {
+                    let e = ShellError::NotExecutable(script, e);
+                    eprintln!("shell: {e}");
+                    return e.status();
+                }
+            };
+            shell.name = script.clone();
+            shell.positional = args.collect();
+            let status = shell.run_lines(&script, text.lines().map(String::from));
+            return shell.run_exit_trap(status);
+        }
+    }
+
+    shell.positional = args.collect();
+    if !io::stdin().is_terminal() {
+        let status = shell.run_lines("stdin", StdinLines);
+        return shell.run_exit_trap(status);
+    }
+    shell.interactive = true;
+    shell.terminal = Terminal::take();
+    if !options.norc {
+        run_rc(&mut shell, options.rcfile, options.posix);
+    }
+    match interact(&mut shell) {
+        // the input ended, which exits like `exit` does
+        Ok(()) => shell.run_exit_trap(shell.status_code),
+        Err(e) => {
+            eprintln!("shell: {e}");
+            1
+        }
+    }
+}
+
+/// Runs `/etc/profile` and `~/.profile`, the files a login shell starts with
+fn run_profiles(shell: &mut Shell) {
+    run_startup_file(shell, Path::new("/etc/profile"), false);
+    if let Some(home) = shell.vars.get("HOME").map(PathBuf::from) {
+        run_startup_file(shell, &home.join(".profile"), false);
+    }
+}
 
-    let mut rl = rustyline::Editor::new().unwrap();
+/// Runs the rc file of an interactive shell: the `rcfile` given on the command line,
+/// otherwise `~/.rshrc` or `$XDG_CONFIG_HOME/rsh/rc`. In POSIX mode it is
+/// the file named by `$ENV` after expanding it instead.
+fn run_rc(shell: &mut Shell, rcfile: Option<String>, posix: bool) {
+    let posix = posix || shell.vars.get("POSIXLY_CORRECT").is_some();
+    if posix {
+        let Some(env) = shell.vars.get("ENV").map(str::to_string) else {
+            return;
+        };
+        let file = match Lexer::new(&env).tokenize() {
+            Ok(tokens) => match tokens.as_slice() {
+                [Token::Word(word)] => shell.expand_word(word),
+                _ => Ok(env),
+            },
+            Err(e) => Err(e.into()),
+        };
+        match file {
+            Ok(file) if !file.is_empty() => run_startup_file(shell, Path::new(&file), false),
+            Ok(_) => {}
+            Err(e) => eprintln!("shell: ENV: {e}"),
+        }
+        return;
+    }
+
+    if let Some(file) = rcfile {
+        run_startup_file(shell, Path::new(&file), true);
+        return;
+    }
+    let home = shell.vars.get("HOME").map(PathBuf::from);
+    if let Some(rc) = home.as_ref().map(|home| home.join(".rshrc")) {
+        if rc.is_file() {
+            run_startup_file(shell, &rc, false);
+            return;
+        }
+    }
+    let config = match shell
+        .vars
+        .get("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+    {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => home.map(|home| home.join(".config")),
+    };
+    if let Some(config) = config {
+        run_startup_file(shell, &config.join("rsh/rc"), false);
+    }
+}
+
+/// Runs a startup file in the shell like `source` does. A file that doesn't
+/// exist is skipped unless it is `required`, and errors in it are reported
+/// with their line without stopping the shell from starting.
+fn run_startup_file(shell: &mut Shell, path: &Path, required: bool) {
+    let name = path.display().to_string();
+    match std::fs::read(path) {
+        Ok(text) => {
+            if let Err(e) = shell.run_source(&name, &text) {
+                eprintln!("shell: {e}");
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {}
+        Err(e) => eprintln!("shell: {}", ShellError::Read(name, e)),
+    }
+}
+
+/// Reads commands with the line editor until the input ends
+fn interact(shell: &mut Shell) -> io::Result<()> {
+    let autocomplete = ShellAutocomplete::new(&shell.path());
+    let mut rl = rustyline::Editor::new().map_err(io::Error::other)?;
     rl.set_completion_type(rustyline::CompletionType::List);
     rl.set_auto_add_history(true);
     rl.set_helper(Some(autocomplete));
-    let _ = shell.read_history(shell.hist_file.clone());
+    if let Some(autocomplete) = rl.helper_mut() {
+        autocomplete.set_aliases(shell.aliases.keys());
+    }
+    let _ = shell.read_history(shell.hist_file());
     let _ = rl.load_history("history.txt");
 
     loop {
+        shell.run_signal_traps();
+        shell.notify_jobs();
         let input = rl.readline("$ ");
         match input {
-            Ok(line) => {
-                shell.execute(line).err().and_then(|e| {
-                    println!("{e}");
-                    Option::<String>::None
-                });
+            Ok(mut line) => {
+                let mut interrupted = false;
+                while let Err(ParseError::Incomplete(_)) = Parser::parse(&line, &shell.aliases) {
+                    match rl.readline("> ") {
+                        Ok(more) => {
+                            line.push('\n');
+                            line.push_str(&more);
+                        }
+                        // Ctrl-C drops the whole command being typed
+                        Err(ReadlineError::Interrupted) => {
+                            interrupted = true;
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                if interrupted {
+                    continue;
+                }
+
+                match shell.execute(line) {
+                    Ok(_) => {}
+                    // the terminal echoed the ^C, which the next prompt shouldn't follow
+                    Err(ShellError::Interrupted) => eprintln!(),
+                    Err(e) => eprintln!("shell: {e}"),
+                }
+                if let Some(autocomplete) = rl.helper_mut() {
+                    autocomplete.set_aliases(shell.aliases.keys());
+                }
             }
-            Err(ReadlineError::Interrupted) => break,
+            // Ctrl-C only clears the line being typed
+            Err(ReadlineError::Interrupted) => continue,
             Err(ReadlineError::Eof) => break,
             Err(err) => {
                 println!("Error: {:?}", err);
@@ -42,3 +310,74 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Runs `f` with a shell whose home is a new directory holding `files`,
+    /// on a stack as large as the shell's own
+    fn with_home(files: &[(&str, &str)], f: fn(&mut Shell)) {
+        let home =
+            std::env::temp_dir().join(format!("rsh-startup-{}-{}", std::process::id(), files[0].0));
+        fs::create_dir_all(&home).unwrap();
+        for (name, text) in files {
+            fs::write(
+                home.join(name),
+                text.replace("$HOME", &home.display().to_string()),
+            )
+            .unwrap();
+        }
+        let dir = home.clone();
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let mut shell = Shell::new();
+                shell.vars.set("HOME", dir.display().to_string()).unwrap();
+                f(&mut shell);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    /// The errors the startup files wrote to `$HOME/errors`
+    fn errors(shell: &Shell) -> Vec<String> {
+        let path = Path::new(shell.vars.get("HOME").unwrap()).join("errors");
+        let text = fs::read_to_string(path).unwrap_or_default();
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn recursive_rc_is_reported() {
+        with_home(&[(".rshrc", ". $HOME/.rshrc 2>>$HOME/errors\n")], |shell| {
+            run_rc(shell, None, false);
+            let errors = errors(shell);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].ends_with("rc: maximum source nesting level exceeded (1000)"));
+            assert_eq!(shell.status_code, 1);
+            assert_eq!(shell.sources, 0);
+            // the shell goes on to read commands
+            assert_eq!(shell.execute("true".to_string()).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn profiles_sourcing_each_other_are_reported() {
+        let files = [
+            (".profile", ". $HOME/.rshrc 2>>$HOME/errors\n"),
+            (".rshrc", ". $HOME/.profile 2>>$HOME/errors\n"),
+        ];
+        with_home(&files, |shell| {
+            run_profiles(shell);
+            let errors = errors(shell);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].ends_with("maximum source nesting level exceeded (1000)"));
+            assert_eq!(shell.status_code, 1);
+            assert_eq!(shell.sources, 0);
+            assert_eq!(shell.execute("true".to_string()).unwrap(), 0);
+        });
+    }
+}
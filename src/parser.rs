@@ -0,0 +1,720 @@
+/// This module contains the recursive descent parser which
+/// turns the tokens from the lexer into a syntax tree
+use crate::ast::{
+    AndOr, CaseItem, CaseTerminator, Command, CompoundCommand, Connector, FunctionDef, List,
+    Pipeline, Redirect, RedirectOp, SimpleCommand, Word, WordPart,
+};
+use crate::lexer::{Lexer, Op, Token};
+use crate::vars::is_name;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The input ended while the given construct was still open
+    Incomplete(String),
+    /// The given token is not allowed where it appeared
+    Unexpected(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Incomplete(s) => {
+                write!(
+                    f,
+                    "syntax error: unexpected end of file while looking for `{s}'"
+                )
+            }
+            ParseError::Unexpected(s) => write!(f, "syntax error near unexpected token `{s}'"),
+        }
+    }
+}
+
+pub struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// The aliases replacing the first word of commands
+    aliases: &'a BTreeMap<String, String>,
+}
+
+impl Parser<'_> {
+    pub fn parse(input: &str, aliases: &BTreeMap<String, String>) -> Result<List, ParseError> {
+        let tokens = Lexer::new(input).tokenize()?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            aliases,
+        };
+        parser.program()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(t) => ParseError::Unexpected(t.describe()),
+            None => ParseError::Unexpected("newline".into()),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn program(&mut self) -> Result<List, ParseError> {
+        let mut list = List::default();
+        self.skip_newlines();
+        while self.peek().is_some() {
+            list.items.push(self.and_or()?);
+            match self.peek() {
+                None | Some(Token::Newline) => self.skip_newlines(),
+                Some(Token::Op(Op::Semi)) => {
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                Some(Token::Op(Op::Amp)) => {
+                    self.background(&mut list);
+                    self.skip_newlines();
+                }
+                Some(_) => return Err(self.unexpected()),
+            }
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+        loop {
+            let connector = match self.peek() {
+                Some(Token::Op(Op::AndIf)) => Connector::And,
+                Some(Token::Op(Op::OrIf)) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                let op = if connector == Connector::And {
+                    "&&"
+                } else {
+                    "||"
+                };
+                return Err(ParseError::Incomplete(op.into()));
+            }
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    /// Consumes the `&` after the last and-or list of `list`, making it a background job
+    fn background(&mut self, list: &mut List) {
+        self.pos += 1;
+        if let Some(and_or) = list.items.last_mut() {
+            and_or.background = true;
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        // each `!` negates the status again, so `! ! cmd` keeps it
+        let mut negated = false;
+        while self.at_keyword(&["!"]) {
+            negated = !negated;
+            self.pos += 1;
+        }
+
+        let mut commands = vec![self.command()?];
+        while self.peek() == Some(&Token::Op(Op::Pipe)) {
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(ParseError::Incomplete("|".into()));
+            }
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias(self.pos, &mut vec![])?;
+        let compound = match self.peek() {
+            Some(Token::Arith(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                CompoundCommand::Arith(word)
+            }
+            Some(Token::Op(Op::LParen)) => {
+                self.pos += 1;
+                let list = self.compound_list(&[], ")", false)?;
+                match self.next() {
+                    Some(Token::Op(Op::RParen)) => CompoundCommand::Subshell(list),
+                    Some(_) => {
+                        self.pos -= 1;
+                        return Err(self.unexpected());
+                    }
+                    None => return Err(ParseError::Incomplete(")".into())),
+                }
+            }
+            Some(Token::Word(word)) => match word.keyword() {
+                Some("{") => {
+                    self.pos += 1;
+                    let list = self.compound_list(&["}"], "}", false)?;
+                    self.expect_keyword("}", "}")?;
+                    CompoundCommand::Group(list)
+                }
+                Some("function") => {
+                    self.pos += 1;
+                    return self.function_def();
+                }
+                None if self.at_function_def() => return self.function_def(),
+                Some("if") => self.if_clause()?,
+                Some("while") => self.while_clause(false)?,
+                Some("until") => self.while_clause(true)?,
+                Some("for") => self.for_clause()?,
+                Some("case") => self.case_clause()?,
+                // the other reserved words can only follow one of the above
+                Some(_) => return Err(self.unexpected()),
+                None => return Ok(Command::Simple(self.simple_command()?)),
+            },
+            _ => return Ok(Command::Simple(self.simple_command()?)),
+        };
+
+        let mut redirects = vec![];
+        while self.at_redirect() {
+            redirects.push(self.redirect()?);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    /// Replaces the word at `pos` with the tokens of the alias it names and returns
+    /// how many tokens took its place. The first word of the value is expanded in
+    /// turn unless it names an alias in `seen`, which are the ones being expanded,
+    /// and a value ending in a blank makes the word following it an alias too.
+    fn expand_alias(&mut self, pos: usize, seen: &mut Vec<String>) -> Result<usize, ParseError> {
+        let name = match self.tokens.get(pos) {
+            Some(Token::Word(w)) => match w.parts.as_slice() {
+                [WordPart::Literal(s)] if w.keyword().is_none() && !seen.contains(s) => s.clone(),
+                _ => return Ok(1),
+            },
+            _ => return Ok(1),
+        };
+        let aliases = self.aliases;
+        let Some(value) = aliases.get(&name) else {
+            return Ok(1);
+        };
+
+        let tokens = Lexer::new(value).tokenize()?;
+        let mut len = tokens.len();
+        seen.push(name);
+        self.tokens.splice(pos..pos + 1, tokens);
+        if len > 0 {
+            len += self.expand_alias(pos, seen)? - 1;
+        }
+        seen.pop();
+
+        if value.ends_with([' ', '\t']) {
+            self.expand_alias(pos + len, &mut vec![])?;
+        }
+        Ok(len)
+    }
+
+    /// Whether the next tokens are `name ( )`
+    fn at_function_def(&self) -> bool {
+        matches!(
+            (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)),
+            (Some(Token::Op(Op::LParen)), Some(Token::Op(Op::RParen)))
+        )
+    }
+
+    /// Parses the name, optional `()` and body of a function definition
+    fn function_def(&mut self) -> Result<Command, ParseError> {
+        let name = match self.next() {
+            Some(Token::Word(w)) if matches!(w.parts.as_slice(), [WordPart::Literal(_)]) => {
+                w.unquoted()
+            }
+            Some(_) => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+            None => return Err(ParseError::Incomplete("}".into())),
+        };
+        if self.peek() == Some(&Token::Op(Op::LParen)) {
+            self.pos += 1;
+            if self.next() != Some(Token::Op(Op::RParen)) {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+        }
+
+        self.skip_newlines();
+        if self.peek().is_none() {
+            return Err(ParseError::Incomplete("}".into()));
+        }
+        let start = self.pos;
+        match self.command()? {
+            Command::Compound(body, redirects) => Ok(Command::Function(Rc::new(FunctionDef {
+                name,
+                body,
+                redirects,
+            }))),
+            // the body has to be a compound command
+            _ => {
+                self.pos = start;
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    /// Whether the next token is one of the given reserved words
+    fn at_keyword(&self, keywords: &[&str]) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) => w.keyword().is_some_and(|k| keywords.contains(&k)),
+            _ => false,
+        }
+    }
+
+    /// Consumes the given reserved word, which the construct ended by `closer` requires next
+    fn expect_keyword(&mut self, keyword: &str, closer: &str) -> Result<(), ParseError> {
+        if self.at_keyword(&[keyword]) {
+            self.pos += 1;
+            Ok(())
+        } else if self.peek().is_none() {
+            Err(ParseError::Incomplete(closer.into()))
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Parses the commands inside a compound command up to one of the reserved
+    /// words in `ends`, a `)` or a `case` terminator, which is left for the caller.
+    /// Only the body of a `case` item may be empty.
+    fn compound_list(
+        &mut self,
+        ends: &[&str],
+        closer: &str,
+        allow_empty: bool,
+    ) -> Result<List, ParseError> {
+        let mut list = List::default();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                None => return Err(ParseError::Incomplete(closer.into())),
+                Some(Token::Op(Op::DSemi | Op::SemiAnd | Op::DSemiAnd | Op::RParen)) => break,
+                _ if self.at_keyword(ends) => break,
+                _ => {}
+            }
+
+            list.items.push(self.and_or()?);
+            match self.peek() {
+                Some(Token::Newline) => {}
+                Some(Token::Op(Op::Semi)) => self.pos += 1,
+                Some(Token::Op(Op::Amp)) => self.background(&mut list),
+                None => return Err(ParseError::Incomplete(closer.into())),
+                _ => break,
+            }
+        }
+
+        if list.items.is_empty() && !allow_empty {
+            return Err(self.unexpected());
+        }
+        Ok(list)
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        let mut branches = vec![];
+        let mut otherwise = None;
+        loop {
+            let condition = self.compound_list(&["then"], "fi", false)?;
+            self.expect_keyword("then", "fi")?;
+            let body = self.compound_list(&["elif", "else", "fi"], "fi", false)?;
+            branches.push((condition, body));
+
+            if self.at_keyword(&["elif"]) {
+                self.pos += 1;
+                continue;
+            }
+            if self.at_keyword(&["else"]) {
+                self.pos += 1;
+                otherwise = Some(self.compound_list(&["fi"], "fi", false)?);
+            }
+            self.expect_keyword("fi", "fi")?;
+            return Ok(CompoundCommand::If {
+                branches,
+                otherwise,
+            });
+        }
+    }
+
+    fn while_clause(&mut self, until: bool) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        let condition = self.compound_list(&["do"], "done", false)?;
+        let body = self.do_group()?;
+        Ok(CompoundCommand::While {
+            until,
+            condition,
+            body,
+        })
+    }
+
+    /// Parses `do list; done`
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_keyword("do", "done")?;
+        let body = self.compound_list(&["done"], "done", false)?;
+        self.expect_keyword("done", "done")?;
+        Ok(body)
+    }
+
+    fn for_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        let name = match self.next() {
+            Some(Token::Arith(word)) => return self.arith_for(word),
+            Some(Token::Word(w)) if is_name(&w.unquoted()) => w.unquoted(),
+            Some(_) => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+            None => return Err(ParseError::Incomplete("done".into())),
+        };
+
+        self.skip_newlines();
+        let mut words = None;
+        if self.at_keyword(&["in"]) {
+            self.pos += 1;
+            let mut list = vec![];
+            while let Some(Token::Word(w)) = self.peek() {
+                list.push(w.clone());
+                self.pos += 1;
+            }
+            words = Some(list);
+            match self.peek() {
+                Some(Token::Newline | Token::Op(Op::Semi)) => self.pos += 1,
+                None => return Err(ParseError::Incomplete("done".into())),
+                Some(_) => return Err(self.unexpected()),
+            }
+        } else if self.peek() == Some(&Token::Op(Op::Semi)) {
+            self.pos += 1;
+        }
+        self.skip_newlines();
+
+        let body = self.do_group()?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    /// Parses the rest of a `for ((init; condition; step))` loop
+    fn arith_for(&mut self, word: Word) -> Result<CompoundCommand, ParseError> {
+        let Some([init, condition, step]) = split_arith_for(word) else {
+            self.pos -= 1;
+            return Err(self.unexpected());
+        };
+        if self.peek() == Some(&Token::Op(Op::Semi)) {
+            self.pos += 1;
+        }
+        self.skip_newlines();
+
+        let body = self.do_group()?;
+        Ok(CompoundCommand::ArithFor {
+            init,
+            condition,
+            step,
+            body,
+        })
+    }
+
+    fn case_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.pos += 1;
+        let word = match self.next() {
+            Some(Token::Word(w)) => w,
+            Some(_) => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+            None => return Err(ParseError::Incomplete("esac".into())),
+        };
+        self.skip_newlines();
+        self.expect_keyword("in", "esac")?;
+
+        let mut items = vec![];
+        loop {
+            self.skip_newlines();
+            if self.at_keyword(&["esac"]) {
+                self.pos += 1;
+                return Ok(CompoundCommand::Case { word, items });
+            }
+            if self.peek() == Some(&Token::Op(Op::LParen)) {
+                self.pos += 1;
+            }
+
+            let mut patterns = vec![];
+            loop {
+                match self.next() {
+                    Some(Token::Word(w)) => patterns.push(w),
+                    None => return Err(ParseError::Incomplete("esac".into())),
+                    Some(_) => {
+                        self.pos -= 1;
+                        return Err(self.unexpected());
+                    }
+                }
+                match self.next() {
+                    Some(Token::Op(Op::Pipe)) => {}
+                    Some(Token::Op(Op::RParen)) => break,
+                    None => return Err(ParseError::Incomplete("esac".into())),
+                    Some(_) => {
+                        self.pos -= 1;
+                        return Err(self.unexpected());
+                    }
+                }
+            }
+
+            let body = self.compound_list(&["esac"], "esac", true)?;
+            let terminator = match self.peek() {
+                Some(Token::Op(Op::DSemi)) => CaseTerminator::Break,
+                Some(Token::Op(Op::SemiAnd)) => CaseTerminator::FallThrough,
+                Some(Token::Op(Op::DSemiAnd)) => CaseTerminator::Continue,
+                // the last item doesn't need a terminator
+                _ if self.at_keyword(&["esac"]) => CaseTerminator::Break,
+                _ => return Err(self.unexpected()),
+            };
+            if !self.at_keyword(&["esac"]) {
+                self.pos += 1;
+            }
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut cmd = SimpleCommand::default();
+        // the command name after assignments can still be an alias
+        let mut after_assignments = false;
+        loop {
+            match self.peek() {
+                Some(Token::Word(_)) => {
+                    if after_assignments && cmd.words.is_empty() {
+                        after_assignments = false;
+                        self.expand_alias(self.pos, &mut vec![])?;
+                        continue;
+                    }
+                    if let Some(Token::Word(w)) = self.next() {
+                        match w.as_assignment() {
+                            Some(a) if cmd.words.is_empty() => {
+                                cmd.assignments.push(a);
+                                after_assignments = true;
+                            }
+                            _ => cmd.words.push(w),
+                        }
+                    }
+                }
+                _ if self.at_redirect() => cmd.redirects.push(self.redirect()?),
+                _ => break,
+            }
+        }
+
+        if cmd.assignments.is_empty() && cmd.words.is_empty() && cmd.redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(cmd)
+    }
+
+    fn at_redirect(&self) -> bool {
+        match self.peek() {
+            Some(Token::IoNumber(_)) => true,
+            Some(Token::Op(op)) => op.is_redirect(),
+            _ => false,
+        }
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek() {
+            Some(Token::IoNumber(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Some(n)
+            }
+            _ => None,
+        };
+
+        let op = match self.peek() {
+            Some(Token::Op(Op::Great | Op::Clobber)) => RedirectOp::Write,
+            Some(Token::Op(Op::DGreat)) => RedirectOp::Append,
+            Some(Token::Op(Op::Less)) => RedirectOp::Read,
+            Some(Token::Op(Op::LessGreat)) => RedirectOp::ReadWrite,
+            Some(Token::Op(Op::GreatAnd)) => RedirectOp::DupOut,
+            Some(Token::Op(Op::LessAnd)) => RedirectOp::DupIn,
+            Some(Token::Op(Op::AndGreat)) if fd.is_none() => RedirectOp::WriteAll,
+            Some(Token::Op(Op::AndDGreat)) if fd.is_none() => RedirectOp::AppendAll,
+            Some(Token::Op(Op::DLess | Op::DLessDash)) => RedirectOp::HereDoc,
+            Some(Token::Op(Op::TLess)) => RedirectOp::HereString,
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+
+        match self.next() {
+            Some(Token::Word(target)) => Ok(Redirect { fd, op, target }),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected())
+            }
+        }
+    }
+}
+
+/// Splits the word inside `for ((init; condition; step))` at its unquoted semicolons
+fn split_arith_for(word: Word) -> Option<[Word; 3]> {
+    let mut words = vec![Word::default()];
+    for part in word.parts {
+        match part {
+            WordPart::Literal(s) => {
+                for (i, piece) in s.split(';').enumerate() {
+                    if i > 0 {
+                        words.push(Word::default());
+                    }
+                    if !piece.is_empty() {
+                        words
+                            .last_mut()?
+                            .parts
+                            .push(WordPart::Literal(piece.into()));
+                    }
+                }
+            }
+            part => words.last_mut()?.parts.push(part),
+        }
+    }
+    words.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `input` with `aliases` and prints the syntax tree back as text
+    fn parse_with(input: &str, aliases: &[(&str, &str)]) -> String {
+        let aliases = aliases
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Parser::parse(input, &aliases).unwrap().to_string()
+    }
+
+    fn parse(input: &str) -> String {
+        parse_with(input, &[])
+    }
+
+    fn error(input: &str) -> ParseError {
+        match Parser::parse(input, &BTreeMap::new()) {
+            Err(e) => e,
+            Ok(list) => panic!("{input:?}: expected an error, parsed {list}"),
+        }
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse("a|b  &&c||d;e&f"), "a | b && c || d; e & f");
+        assert_eq!(parse("a\n\nb\n"), "a; b");
+        assert_eq!(parse("a &&\nb |\nc"), "a && b | c");
+        assert_eq!(parse("echo '|' \";\" \\&"), "echo '|' \";\" '&'");
+    }
+
+    #[test]
+    fn redirections() {
+        assert_eq!(
+            parse("cmd 2>err >out 2>&1 <in"),
+            "cmd 2> err > out 2>&1 < in"
+        );
+        assert_eq!(parse("a2>x"), "a2 > x");
+        assert_eq!(parse("{ a; } >out"), "{ a; } > out");
+    }
+
+    #[test]
+    fn negation() {
+        let negated = |input: &str| {
+            Parser::parse(input, &BTreeMap::new()).unwrap().items[0]
+                .first
+                .negated
+        };
+        assert!(negated("! true"));
+        assert!(!negated("! ! true"));
+        assert!(negated("! ! ! true"));
+        assert!(!negated("true"));
+        // `!` only counts in front of a pipeline
+        assert_eq!(parse("echo !"), "echo !");
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for (input, missing) in [
+            ("a &&", "&&"),
+            ("a ||\n", "||"),
+            ("a |", "|"),
+            ("( a", ")"),
+            ("{ a;", "}"),
+            ("if a; then b", "fi"),
+            ("while a; do b", "done"),
+            ("case x in", "esac"),
+            ("f() ", "}"),
+        ] {
+            match error(input) {
+                ParseError::Incomplete(s) => assert_eq!(s, missing, "{input:?}"),
+                e => panic!("{input:?}: expected incomplete input, got {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (input, token) in [
+            ("a | | b", "|"),
+            ("; a", ";"),
+            ("a && && b", "&&"),
+            (")", ")"),
+            ("fi", "fi"),
+            ("if a; then b; done", "done"),
+            ("a >", "newline"),
+            ("( )", ")"),
+        ] {
+            match error(input) {
+                ParseError::Unexpected(s) => assert_eq!(s, token, "{input:?}"),
+                e => panic!("{input:?}: expected a syntax error, got {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = [
+            ("ll", "ls -l"),
+            ("ls", "ls -F"),
+            ("s", "sudo "),
+            ("q", "quiet"),
+            ("loop1", "loop2"),
+            ("loop2", "loop1"),
+        ];
+        assert_eq!(parse_with("ll /", &aliases), "ls -F -l /");
+        // a value ending in a blank makes the next word an alias too
+        assert_eq!(parse_with("s ll", &aliases), "sudo ls -F -l");
+        assert_eq!(parse_with("s s q", &aliases), "sudo sudo quiet");
+        // other arguments are not aliases
+        assert_eq!(parse_with("echo ll", &aliases), "echo ll");
+        assert_eq!(parse_with("ll; ll", &aliases), "ls -F -l; ls -F -l");
+        assert_eq!(parse_with("'ll'", &aliases), "'ll'");
+        assert_eq!(parse_with("loop1", &aliases), "loop1");
+    }
+}
@@ -1,71 +1,1058 @@
-use crate::builtins::ChildOrStatus;
+use crate::arith;
+use crate::ast::{
+    self, AndOr, Assignment, CaseItem, CaseTerminator, CompoundCommand, Connector, FunctionDef,
+    List, Pipeline, Redirect, SimpleCommand, Word,
+};
+use crate::builtins::{exit_status, report_signal, ChildOrStatus};
 use crate::command::Command;
+use crate::error::ShellError;
+use crate::glob::Pattern;
+use crate::jobs::{JobState, Jobs, Terminal};
+use crate::parser::{ParseError, Parser};
 use crate::shell_io::{Input, Output};
+use crate::signals;
+use crate::traps::{Trap, Traps};
+use crate::vars::{Var, Variables};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::pipe;
+use std::io::{self, pipe, ErrorKind, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::rc::Rc;
 
 #[derive(Default)]
 pub struct Shell {
-    status_code: i32,
-    pub path: Vec<PathBuf>,
+    /// `$0`, the name the shell was started as or of the script it runs
+    pub name: String,
+    /// Whether commands are read from a terminal, as opposed to a script or `-c`
+    pub interactive: bool,
+    pub status_code: i32,
+    pub vars: Variables,
+    /// The positional parameters `$1`, `$2` and so on
+    pub positional: Vec<String>,
     pub pwd: PathBuf,
-    pub hist_file: String,
     pub history: Vec<String>,
     pub appended: usize,
+    /// The options turned on with `shopt -s`
+    pub shopts: HashSet<String>,
+    /// The pid of the shell, which `$$` keeps expanding to in forked copies
+    pub pid: u32,
+    /// Whether this is a forked copy of the shell, e.g. running a command substitution
+    pub subshell: bool,
+    /// The status of the last command substitution in the command being expanded
+    pub substitution_status: Option<i32>,
+    /// The number of loops around the command being run
+    pub loops: usize,
+    /// A `break`, `continue` or `return` on its way out to the loop or function it applies to
+    pub flow: Option<Flow>,
+    pub functions: HashMap<String, Rc<FunctionDef>>,
+    /// The aliases defined with `alias`, sorted for listing them
+    pub aliases: BTreeMap<String, String>,
+    /// The number of files being run by `source`
+    pub sources: usize,
+    /// The name and line of the command being run, like `script.sh:12`,
+    /// when it was read by `run_lines`
+    pub location: Option<String>,
+    /// The variables each running function declared with `local`,
+    /// along with the values to put back when it returns
+    pub locals: Vec<Vec<(String, Option<Var>)>>,
+    /// The pipelines running in the background or stopped
+    pub jobs: Jobs,
+    /// `$!`, the pid of the last job started in the background
+    pub last_background: Option<libc::pid_t>,
+    /// Whether a forked copy of the shell has nothing left to do after the
+    /// next simple command, so an external program can replace it
+    pub exec: bool,
+    /// The terminal of an interactive shell with job control
+    pub terminal: Option<Terminal>,
+    /// The process group of the pipeline being started, once its first process is
+    pub pgid: Option<libc::pid_t>,
+    pub traps: Traps,
+    /// Whether a trap is running, during which no other trap but EXIT runs
+    pub trapping: bool,
+    /// The number of `if`, `while` and `until` conditions being run,
+    /// where a failing command doesn't run the ERR trap
+    pub testing: usize,
+    /// The pipe ends of the later stages of the pipeline being started,
+    /// which the forked copies of the shell running earlier stages close
+    pub pipe_fds: Vec<RawFd>,
+}
+
+/// How deeply functions can call each other before the call fails
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// How deeply sourced files can source each other before `source` fails
+const MAX_SOURCE_DEPTH: usize = 1000;
+
+/// How the shell leaves the commands it is running before reaching their end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// `break n`, the number of loops still to leave
+    Break(usize),
+    /// `continue n`, the number of loops to leave before resuming the enclosing one
+    Continue(usize),
+    /// `return`, leaving the function being run
+    Return,
 }
 
 impl Shell {
     pub fn new() -> Self {
-        if let Ok(path_os_string) = std::env::var("PATH") {
-            let paths: Vec<PathBuf> = std::env::split_paths(&path_os_string).collect();
-            let hist_file = std::env::var("HISTFILE").unwrap_or_default();
+        Shell {
+            name: std::env::args().next().unwrap_or_default(),
+            interactive: false,
+            status_code: 0,
+            vars: Variables::from_env(),
+            positional: vec![],
+            pwd: std::env::current_dir().unwrap_or_default(),
+            history: vec![],
+            appended: 0,
+            shopts: HashSet::new(),
+            pid: std::process::id(),
+            subshell: false,
+            substitution_status: None,
+            loops: 0,
+            flow: None,
+            functions: HashMap::new(),
+            aliases: BTreeMap::new(),
+            sources: 0,
+            location: None,
+            jobs: Jobs::default(),
+            last_background: None,
+            exec: false,
+            terminal: None,
+            pgid: None,
+            traps: Traps::default(),
+            trapping: false,
+            testing: 0,
+            pipe_fds: vec![],
+            locals: vec![],
+        }
+    }
 
-            Shell {
-                status_code: 0,
-                path: paths.into_iter().filter(|p| p.is_dir()).collect(),
-                pwd: std::env::current_dir().unwrap(),
-                hist_file,
-                history: vec![],
-                appended: 0,
-            }
-        } else {
-            Shell::default()
+    /// The directories listed in `$PATH`
+    pub fn path(&self) -> Vec<PathBuf> {
+        match self.vars.get("PATH") {
+            Some(path) => std::env::split_paths(path).filter(|p| p.is_dir()).collect(),
+            None => vec![],
         }
     }
 
-    pub fn execute(&mut self, input: String) -> std::io::Result<i32> {
+    pub fn hist_file(&self) -> String {
+        self.vars.get("HISTFILE").unwrap_or_default().to_string()
+    }
+
+    pub fn execute(&mut self, input: String) -> Result<i32, ShellError> {
         self.history.push(input.clone());
 
-        let mut cmd = Command::from(input);
+        let r = Parser::parse(&input, &self.aliases)
+            .map_err(ShellError::from)
+            .and_then(|list| {
+                self.run_list(&list)?;
+                Ok(self.status_code)
+            });
+        r.inspect_err(|e| self.status_code = e.status())
+    }
+
+    /// Runs the commands read from `lines` one complete command at a time, as if they
+    /// were typed in, and returns the status of the last one. Errors are reported
+    /// with the `name` of the input and the line the command started on, and a
+    /// syntax error stops reading.
+    pub fn run_lines(&mut self, name: &str, mut lines: impl Iterator<Item = String>) -> i32 {
+        let mut number = 0;
+        // the status when there are no commands at all
+        let mut status = 0;
+        let outer = self.location.take();
+        while let Some(mut input) = lines.next() {
+            number += 1;
+            let start = number;
+
+            let r = loop {
+                match Parser::parse(&input, &self.aliases) {
+                    Err(ParseError::Incomplete(_)) => match lines.next() {
+                        Some(line) => {
+                            number += 1;
+                            input.push('\n');
+                            input.push_str(&line);
+                        }
+                        None => break Parser::parse(&input, &self.aliases),
+                    },
+                    r => break r,
+                }
+            };
+
+            self.location = Some(format!("{name}:{start}"));
+            let r = r.map_err(ShellError::from).and_then(|list| {
+                if !list.items.is_empty() {
+                    self.run_list(&list)?;
+                    status = self.status_code;
+                }
+                Ok(())
+            });
+            // Ctrl-C leaves the rest of the input, which needs no message
+            if let Err(ShellError::Interrupted) = r {
+                self.status_code = ShellError::Interrupted.status();
+                status = self.status_code;
+                break;
+            }
+            if let Err(e) = r {
+                eprintln!("shell: {name}:{start}: {e}");
+                self.status_code = e.status();
+                status = self.status_code;
+                if let ShellError::Syntax(_) = e {
+                    break;
+                }
+            }
+            // a `break`, `continue` or `return` leaves the rest of the input too
+            if self.flow.is_some() {
+                break;
+            }
+        }
+        self.location = outer;
+        status
+    }
+
+    /// Runs the contents of a file read by `source` or at startup, where
+    /// `return` leaves the file
+    pub fn run_source(&mut self, name: &str, text: &[u8]) -> Result<i32, ShellError> {
+        if self.sources >= MAX_SOURCE_DEPTH {
+            return Err(ShellError::SourceNesting(
+                name.to_string(),
+                MAX_SOURCE_DEPTH,
+            ));
+        }
+        let text = String::from_utf8_lossy(text);
+        self.sources += 1;
+        let status = self.run_lines(name, text.lines().map(String::from));
+        self.sources -= 1;
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        self.run_trap(Trap::Return);
+        Ok(status)
+    }
+
+    pub fn run_list(&mut self, list: &List) -> Result<(), ShellError> {
+        for and_or in &list.items {
+            if self.flow.is_some() {
+                break;
+            }
+            match and_or.background {
+                true => self.run_background(and_or)?,
+                false => self.run_and_or(and_or)?,
+            }
+            self.run_signal_traps();
+        }
+        Ok(())
+    }
+
+    /// Runs the command of a trap unless another trap is running, keeping `$?`
+    pub fn run_trap(&mut self, trap: Trap) {
+        let Some(command) = self.traps.get(trap).filter(|c| !c.is_empty()) else {
+            return;
+        };
+        if self.trapping {
+            return;
+        }
+        let command = command.to_string();
+        let status = self.status_code;
+        self.trapping = true;
+        self.run_lines("trap", command.lines().map(String::from));
+        self.trapping = false;
+        self.status_code = status;
+    }
+
+    /// Runs the traps of the signals that arrived since they last ran,
+    /// which is done between commands so they never interrupt one
+    pub fn run_signal_traps(&mut self) {
+        if self.trapping {
+            return;
+        }
+        for signo in signals::take_pending() {
+            self.run_trap(Trap::Signal(signo));
+        }
+    }
+
+    /// Runs the EXIT trap as the shell exits with `status`, and returns the
+    /// status to exit with, which only changes if the trap calls `exit`
+    pub fn run_exit_trap(&mut self, status: i32) -> i32 {
+        let Some(command) = self.traps.take(Trap::Exit).filter(|c| !c.is_empty()) else {
+            return status;
+        };
+        self.status_code = status;
+        self.flow = None;
+        self.trapping = true;
+        self.run_lines("trap", command.lines().map(String::from));
+        self.trapping = false;
+        status
+    }
+
+    /// Starts `cmd &` as a job in a forked copy of the shell in a process group
+    /// of its own, without waiting for it
+    fn run_background(&mut self, and_or: &AndOr) -> Result<(), ShellError> {
+        let pid = self.fork(|shell| {
+            // SAFETY: setpgid only changes the process group of this process
+            unsafe { libc::setpgid(0, 0) };
+            if shell.terminal.is_some() {
+                signals::default_job_signals();
+            }
+            shell.jobs = Jobs::default();
+            // without job control a job can't read from the terminal
+            if !shell.interactive {
+                if let Ok(null) = fs::File::open("/dev/null") {
+                    // SAFETY: dup2 only replaces the descriptor 0 of this process
+                    unsafe { libc::dup2(null.as_raw_fd(), 0) };
+                }
+            }
+            // a lone simple command replaces the copy, so `$!` is its pid
+            let [first] = and_or.first.commands.as_slice() else {
+                return shell.run_job(and_or);
+            };
+            shell.exec = and_or.rest.is_empty()
+                && !and_or.first.negated
+                && matches!(first, ast::Command::Simple(_));
+            shell.run_job(and_or)
+        })?;
+        // set here as well, so the group exists before anyone refers to it
+        // SAFETY: setpgid only changes the process group of the child
+        unsafe { libc::setpgid(pid, pid) };
+
+        let id = self.jobs.add(pid, &[pid], and_or.to_string());
+        self.last_background = Some(pid);
+        if self.interactive {
+            eprintln!("[{id}] {pid}");
+        }
+        self.status_code = 0;
+        Ok(())
+    }
+
+    /// Runs the commands of a background job in the copy of the shell running it
+    fn run_job(&mut self, and_or: &AndOr) -> i32 {
+        match self.run_and_or(and_or) {
+            Ok(()) => self.status_code,
+            Err(e) => {
+                eprintln!("shell: {e}");
+                e.status()
+            }
+        }
+    }
+
+    /// Waits for a job to finish or stop and returns its state. A finished
+    /// job leaves the table, a stopped one is reported.
+    pub fn wait_job(&mut self, id: usize) -> io::Result<JobState> {
+        let Some(job) = self.jobs.get_mut(id) else {
+            return Ok(JobState::Exited(127));
+        };
+        job.update(true)?;
+        let state = job.state();
+        match state {
+            JobState::Running => {}
+            JobState::Stopped(_) => {
+                self.jobs.touch(id);
+                if let Some(job) = self.jobs.get(id) {
+                    eprintln!("\n{}", self.jobs.format(job, false));
+                }
+            }
+            _ => {
+                self.jobs.remove(id);
+            }
+        }
+        Ok(state)
+    }
 
-        let mut cmds = cmd
-            .args
-            .split(|x| x == "|")
-            .map(|args| Command::new(args[0].clone()).with_args(args.to_vec()))
+    /// Whether pipelines run as jobs in process groups of their own, which
+    /// takes an interactive shell that isn't a forked copy
+    pub fn job_control(&self) -> bool {
+        self.terminal.is_some() && !self.subshell
+    }
+
+    /// Runs a job in the foreground, giving it the terminal until it finishes or
+    /// stops, and returns its state. `resume` continues a stopped job first.
+    pub fn run_foreground(&mut self, id: usize, resume: bool) -> io::Result<JobState> {
+        let Some(job) = self.jobs.get(id) else {
+            return Ok(JobState::Exited(127));
+        };
+        if let (Some(terminal), false) = (&self.terminal, self.subshell) {
+            terminal.give(job.pgid, job.termios.as_ref());
+        }
+        let r = match resume {
+            true => self.continue_job(id),
+            false => Ok(()),
+        };
+        // a trapped signal interrupts the wait, but its trap waits for the job
+        let r = r.and_then(|_| loop {
+            match self.wait_job(id) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                r => break r,
+            }
+        });
+        if let (Some(terminal), false) = (&self.terminal, self.subshell) {
+            // a stopped job gets its modes back when it is continued
+            if let Some(job) = self.jobs.get_mut(id) {
+                job.termios = Terminal::modes();
+            }
+            terminal.reclaim();
+        }
+        r
+    }
+
+    /// Waits for the processes a pipeline started with job control, as a job
+    /// that stays in the table if it is stopped. Returns the status of the
+    /// last command, which may have run in the shell itself.
+    fn wait_foreground(
+        &mut self,
+        stages: Vec<ChildOrStatus>,
+        command: String,
+    ) -> Result<i32, ShellError> {
+        let last = match stages.last() {
+            Some(ChildOrStatus::Status(status)) => Some(*status),
+            _ => None,
+        };
+        let pids = stages
+            .iter()
+            .filter_map(ChildOrStatus::pid)
             .collect::<Vec<_>>();
+        let pgid = self.pgid.take().unwrap_or(pids[0]);
+        let id = self.jobs.add(pgid, &pids, command);
+        let state = self.run_foreground(id, false)?;
+        if let JobState::Killed(signo, core) = state {
+            report_signal(signo, core);
+            // like the shell itself was interrupted, which leaves the whole command
+            if signo == libc::SIGINT {
+                return Err(ShellError::Interrupted);
+            }
+        }
+        Ok(last.unwrap_or(state.status()))
+    }
+
+    /// Sends SIGCONT to the process group of a job and marks it running again
+    pub fn continue_job(&mut self, id: usize) -> io::Result<()> {
+        self.jobs.touch(id);
+        let Some(pgid) = self.jobs.get(id).map(|job| job.pgid) else {
+            return Ok(());
+        };
+        // SAFETY: kill only sends a signal
+        if unsafe { libc::kill(-pgid, libc::SIGCONT) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.jobs.continued(-pgid);
+        Ok(())
+    }
+
+    /// Reports the jobs that finished since they were last checked on and
+    /// removes them from the table, before the next prompt
+    pub fn notify_jobs(&mut self) {
+        self.jobs.update();
+        for id in self.jobs.ids() {
+            let Some(job) = self.jobs.get(id) else {
+                continue;
+            };
+            if job.state().is_done() {
+                eprintln!("{}", self.jobs.format(job, false));
+                self.jobs.remove(id);
+            }
+        }
+    }
+
+    /// Runs `f` in a forked copy of the shell, which exits with the status `f`
+    /// returns, and returns the pid of the copy
+    pub fn fork(&mut self, f: impl FnOnce(&mut Shell) -> i32) -> io::Result<libc::pid_t> {
+        // anything still buffered would otherwise be written by both processes
+        io::stdout().flush()?;
+
+        // SAFETY: the child only gets this thread. The only other one is the main
+        // thread, which does nothing but wait in `join` for this one, with every
+        // signal blocked, so it holds no lock the child could find taken forever.
+        // The child only uses the state this thread owns, until it execs or exits.
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                self.subshell = true;
+                // a pipe only sees its end once every copy of its writing end is
+                // closed, and only gets SIGPIPE once every reading end is
+                for fd in std::mem::take(&mut self.pipe_fds) {
+                    // SAFETY: the descriptors belong to commands the copy never runs or drops
+                    unsafe { libc::close(fd) };
+                }
+                self.traps.forget_commands(self.terminal.is_some());
+                // like the programs it runs, the copy is killed by writing to
+                // a pipe nobody reads, e.g. to end a loop feeding `head`
+                if self.traps.get(Trap::Signal(libc::SIGPIPE)) != Some("") {
+                    signals::reset(libc::SIGPIPE, false);
+                }
+                let status = f(self);
+                let status = self.run_exit_trap(status);
+                let _ = io::stdout().flush();
+                // SAFETY: _exit skips the exit handlers which belong to the parent
+                unsafe { libc::_exit(status) }
+            }
+            pid => Ok(pid),
+        }
+    }
+
+    /// Runs `f` in a forked copy of the shell like `fork`, as a process of the pipeline
+    /// being started. With job control it joins the process group of the pipeline,
+    /// or starts it, and gets the default action for the signals from the keyboard.
+    pub fn fork_job(&mut self, f: impl FnOnce(&mut Shell) -> i32) -> io::Result<libc::pid_t> {
+        if !self.job_control() {
+            return self.fork(f);
+        }
+        let pgid = self.pgid.unwrap_or(0);
+        let pid = self.fork(|shell| {
+            // SAFETY: setpgid only changes the process group of this process
+            unsafe { libc::setpgid(0, pgid) };
+            signals::default_job_signals();
+            f(shell)
+        })?;
+        // set here as well, so the group exists before the next process joins it
+        // SAFETY: setpgid only changes the process group of the child
+        unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+        Ok(pid)
+    }
+
+    /// Waits for a child created with `fork` to terminate
+    pub fn wait_pid(pid: libc::pid_t) -> io::Result<ExitStatus> {
+        let mut status = 0;
+        // SAFETY: waitpid only writes to `status`
+        while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(ExitStatus::from_raw(status))
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr) -> Result<(), ShellError> {
+        self.run_pipeline(&and_or.first)?;
+        let mut last = Some(&and_or.first);
+        for (connector, pipeline) in &and_or.rest {
+            let run = match connector {
+                Connector::And => self.status_code == 0,
+                Connector::Or => self.status_code != 0,
+            };
+            last = None;
+            if run && self.flow.is_none() {
+                self.run_pipeline(pipeline)?;
+                last = Some(pipeline);
+            }
+        }
+        // only the last pipeline of `a && b || c` is tested by nothing else
+        if let Some(pipeline) = last {
+            self.trap_err(pipeline);
+        }
+        Ok(())
+    }
+
+    /// Runs the ERR trap after a pipeline failed, unless it was negated, is part of a
+    /// condition or is a compound command whose failing command already ran the trap
+    fn trap_err(&mut self, pipeline: &Pipeline) {
+        if self.status_code == 0 || pipeline.negated || self.testing > 0 || self.flow.is_some() {
+            return;
+        }
+        if let [ast::Command::Compound(compound, _)] = pipeline.commands.as_slice() {
+            if !matches!(compound, CompoundCommand::Subshell(_)) {
+                return;
+            }
+        }
+        self.run_trap(Trap::Err);
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> Result<(), ShellError> {
+        self.run_stages(pipeline)?;
+        if pipeline.negated {
+            self.status_code = (self.status_code == 0) as i32;
+        }
+        Ok(())
+    }
 
-        let n = cmds.len();
+    fn run_stages(&mut self, pipeline: &Pipeline) -> Result<(), ShellError> {
+        // a lone compound command runs in the shell itself, so it can change its state
+        match pipeline.commands.as_slice() {
+            [ast::Command::Compound(compound, redirects)] => {
+                self.status_code = self.run_redirected(compound, redirects)?;
+                return Ok(());
+            }
+            [ast::Command::Function(def)] => {
+                self.functions.insert(def.name.clone(), def.clone());
+                self.status_code = 0;
+                return Ok(());
+            }
+            _ => {}
+        }
 
-        cmds[n - 1].stderr = cmd.stderr.take();
-        cmds[n - 1].stdout = cmd.stdout.take();
+        let exec = std::mem::take(&mut self.exec);
+        if let [ast::Command::Simple(simple)] = pipeline.commands.as_slice() {
+            // a lone simple command is expanded by the shell itself, so it
+            // can change its state, e.g. with `: $((x = 1))`
+            self.run_trap(Trap::Debug);
+            let cmd = self.expand_command(simple, exec)?;
+            self.pgid = None;
+            let r = self.run_simple(cmd, simple);
+            return self.wait_stages(vec![r], pipeline);
+        }
 
-        for i in 1..n {
+        let mut stages = pipeline
+            .commands
+            .iter()
+            .map(|_| Command::new(String::new()))
+            .collect::<Vec<_>>();
+        for i in 1..stages.len() {
             let (pi, po) = pipe()?;
-            cmds[i - 1].stdout = Output::Pipe(po);
-            cmds[i].stdin = Input::Pipe(pi);
+            stages[i - 1].stdout = Output::Pipe(po);
+            stages[i].stdin = Input::Pipe(pi);
         }
 
-        let r = cmds
+        self.pgid = None;
+        let fds = stages.iter().map(Command::pipe_fds).collect::<Vec<_>>();
+        let r = stages
             .into_iter()
-            .map(|mut cmd| cmd.execute(self))
-            .collect::<std::io::Result<Vec<ChildOrStatus>>>()?;
+            .zip(&pipeline.commands)
+            .enumerate()
+            .map(|(i, (io, command))| {
+                // the stages before this one already let go of their pipes
+                self.pipe_fds = fds[i + 1..].concat();
+                let r = match command {
+                    ast::Command::Simple(simple) => {
+                        self.run_trap(Trap::Debug);
+                        self.spawn_simple(io, simple)
+                    }
+                    ast::Command::Compound(compound, redirects) => {
+                        self.spawn_compound(io, compound, redirects)
+                    }
+                    // a definition in a pipeline would only happen in a copy of the shell
+                    ast::Command::Function(_) => ChildOrStatus::Status(0),
+                };
+                // the first process starts the process group of the pipeline
+                if self.pgid.is_none() {
+                    self.pgid = r.pid();
+                }
+                r
+            })
+            .collect::<Vec<_>>();
+        self.pipe_fds.clear();
+        self.wait_stages(r, pipeline)
+    }
 
+    /// Waits for the commands of a pipeline, in the foreground with job control,
+    /// and takes the status of the last one
+    fn wait_stages(
+        &mut self,
+        r: Vec<ChildOrStatus>,
+        pipeline: &Pipeline,
+    ) -> Result<(), ShellError> {
+        if self.job_control() && r.iter().any(|x| x.pid().is_some()) {
+            self.status_code = self.wait_foreground(r, pipeline.to_string())?;
+            return Ok(());
+        }
         for x in r {
             self.status_code = x.wait();
         }
+        Ok(())
+    }
+
+    /// Expands the words of a simple command into the command to run, which
+    /// replaces the shell on `exec` when the shell has nothing left to do
+    fn expand_command(
+        &mut self,
+        simple: &SimpleCommand,
+        exec: bool,
+    ) -> Result<Command, ShellError> {
+        self.substitution_status = None;
+        let mut args = vec![];
+        for word in &simple.words {
+            args.extend(self.expand_fields(word)?);
+        }
+        let mut cmd = Command::from(args);
+        cmd.exec = exec;
+        Ok(cmd)
+    }
+
+    fn run_simple(&mut self, mut cmd: Command, simple: &SimpleCommand) -> ChildOrStatus {
+        let r = self.assign(&simple.assignments, !cmd.args.is_empty());
+        let r = r.and_then(|saved| {
+            let r = cmd
+                .redirect(&simple.redirects, self)
+                .and_then(|_| cmd.execute(self));
+            self.restore(saved);
+            r
+        });
+        match r {
+            // a line of only assignments ends with the status of its last substitution
+            Ok(ChildOrStatus::Status(0)) if cmd.args.is_empty() => {
+                ChildOrStatus::Status(self.substitution_status.unwrap_or(0))
+            }
+            Ok(r) => r,
+            // a builtin writing into a pipe nobody reads, e.g. because the
+            // next stage redirected its stdin, ends like a process on SIGPIPE
+            Err(ShellError::Builtin(_, e)) if e.kind() == ErrorKind::BrokenPipe => {
+                ChildOrStatus::Status(141)
+            }
+            Err(e) => {
+                cmd.report(&e, self.location.as_deref());
+                ChildOrStatus::Status(e.status())
+            }
+        }
+    }
+
+    /// Runs a compound command with its redirections placed on the shell's own descriptors
+    fn run_redirected(
+        &mut self,
+        compound: &CompoundCommand,
+        redirects: &[Redirect],
+    ) -> Result<i32, ShellError> {
+        if redirects.is_empty() {
+            return self.run_compound(compound);
+        }
+
+        let mut io = Command::new(String::new());
+        if let Err(e) = io.redirect(redirects, self) {
+            io.report(&e, self.location.as_deref());
+            return Ok(e.status());
+        }
+        let saved = io.install()?;
+        let r = self.run_compound(compound);
+        saved.restore();
+        r
+    }
+
+    /// Runs a simple command that is part of a pipeline in a forked copy of the shell,
+    /// which expands its words itself, so that like in a subshell their side effects
+    /// such as `$((x = 1))` don't reach the shell
+    fn spawn_simple(&mut self, mut io: Command, simple: &SimpleCommand) -> ChildOrStatus {
+        let pid = self.fork_job(|shell| {
+            // the descriptors of the copy are its own, so they are never restored
+            let r = io
+                .install()
+                .map_err(ShellError::from)
+                .and_then(|_| shell.expand_command(simple, true));
+            match r {
+                Ok(cmd) => shell.run_simple(cmd, simple).wait(),
+                Err(e) => {
+                    io.report(&e, shell.location.as_deref());
+                    e.status()
+                }
+            }
+        });
+        match pid {
+            Ok(pid) => ChildOrStatus::Forked(pid),
+            Err(e) => {
+                eprintln!("shell: {e}");
+                ChildOrStatus::Status(1)
+            }
+        }
+    }
+
+    /// Runs a compound command that is part of a pipeline in a forked copy of the shell
+    fn spawn_compound(
+        &mut self,
+        mut io: Command,
+        compound: &CompoundCommand,
+        redirects: &[Redirect],
+    ) -> ChildOrStatus {
+        let pid = self.fork_job(|shell| {
+            // the descriptors of the copy are its own, so they are never restored
+            let r = io
+                .redirect(redirects, shell)
+                .and_then(|_| Ok(io.install()?))
+                .and_then(|_| shell.run_compound(compound));
+            match r {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("shell: {e}");
+                    e.status()
+                }
+            }
+        });
+        match pid {
+            Ok(pid) => ChildOrStatus::Forked(pid),
+            Err(e) => {
+                eprintln!("shell: {e}");
+                ChildOrStatus::Status(1)
+            }
+        }
+    }
+
+    fn run_compound(&mut self, compound: &CompoundCommand) -> Result<i32, ShellError> {
+        match compound {
+            CompoundCommand::Group(list) => self.run_body(list),
+            CompoundCommand::Subshell(list) => {
+                self.pgid = None;
+                let pid = self.fork_job(|shell| match shell.run_list(list) {
+                    Ok(()) => shell.status_code,
+                    Err(e) => {
+                        eprintln!("shell: {e}");
+                        e.status()
+                    }
+                })?;
+                if self.job_control() {
+                    let stages = vec![ChildOrStatus::Forked(pid)];
+                    return self.wait_foreground(stages, compound.to_string());
+                }
+                Ok(exit_status(Shell::wait_pid(pid)?))
+            }
+            CompoundCommand::Arith(word) => match self.eval_arith(word) {
+                Ok(value) => Ok(if value.unwrap_or(0) != 0 { 0 } else { 1 }),
+                Err(e) => self.arith_failed(e),
+            },
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    self.run_condition(condition)?;
+                    if self.flow.is_some() {
+                        return Ok(self.status_code);
+                    }
+                    if self.status_code == 0 {
+                        return self.run_body(body);
+                    }
+                }
+                match otherwise {
+                    Some(body) => self.run_body(body),
+                    None => Ok(0),
+                }
+            }
+            CompoundCommand::Case { word, items } => self.run_case(word, items),
+            _ => {
+                self.loops += 1;
+                let r = self.run_loop(compound);
+                self.loops -= 1;
+                r
+            }
+        }
+    }
+
+    /// Runs the condition of an `if`, `while` or `until`, whose failure is expected
+    fn run_condition(&mut self, condition: &List) -> Result<(), ShellError> {
+        self.testing += 1;
+        let r = self.run_list(condition);
+        self.testing -= 1;
+        r
+    }
+
+    /// Runs the commands of a compound command, returning 0 if there are none
+    fn run_body(&mut self, body: &List) -> Result<i32, ShellError> {
+        if body.items.is_empty() {
+            return Ok(0);
+        }
+        self.run_list(body)?;
+        Ok(self.status_code)
+    }
+
+    /// Runs a `while`, `until` or `for` loop, returning the status
+    /// of the last run of its body or 0 if it never ran
+    fn run_loop(&mut self, compound: &CompoundCommand) -> Result<i32, ShellError> {
+        let mut status = 0;
+        match compound {
+            CompoundCommand::While {
+                until,
+                condition,
+                body,
+            } => loop {
+                self.run_condition(condition)?;
+                if self.loop_interrupted() {
+                    break;
+                }
+                if (self.status_code == 0) == *until {
+                    break;
+                }
+                status = self.run_body(body)?;
+                if self.loop_interrupted() {
+                    break;
+                }
+            },
+            CompoundCommand::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => {
+                        let mut values = vec![];
+                        for word in words {
+                            values.extend(self.expand_fields(word)?);
+                        }
+                        values
+                    }
+                    None => self.positional.clone(),
+                };
+                for value in values {
+                    self.vars.set(name, value)?;
+                    status = self.run_body(body)?;
+                    if self.loop_interrupted() {
+                        break;
+                    }
+                }
+            }
+            CompoundCommand::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Err(e) = self.eval_arith(init) {
+                    return self.arith_failed(e);
+                }
+                loop {
+                    match self.eval_arith(condition) {
+                        // an empty condition is true
+                        Ok(value) if value.unwrap_or(1) != 0 => {}
+                        Ok(_) => break,
+                        Err(e) => return self.arith_failed(e),
+                    }
+                    status = self.run_body(body)?;
+                    if self.loop_interrupted() {
+                        break;
+                    }
+                    if let Err(e) = self.eval_arith(step) {
+                        return self.arith_failed(e);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(status)
+    }
 
-        Ok(0)
+    /// Consumes a pending `break` or `continue` that applies to the loop being run,
+    /// returning whether the loop has to stop
+    fn loop_interrupted(&mut self) -> bool {
+        match self.flow.take() {
+            None | Some(Flow::Continue(1)) => false,
+            Some(Flow::Return) => {
+                self.flow = Some(Flow::Return);
+                true
+            }
+            Some(Flow::Break(1)) => true,
+            Some(Flow::Break(n)) => {
+                self.flow = Some(Flow::Break(n - 1));
+                true
+            }
+            Some(Flow::Continue(n)) => {
+                self.flow = Some(Flow::Continue(n - 1));
+                true
+            }
+        }
+    }
+
+    fn run_case(&mut self, word: &Word, items: &[CaseItem]) -> Result<i32, ShellError> {
+        let subject = self.expand_word(word)?;
+        let mut status = 0;
+        // set after `;&`, to run the next item without testing its patterns
+        let mut fall_through = false;
+
+        for item in items {
+            if !fall_through {
+                let mut matched = false;
+                for pattern in &item.patterns {
+                    if Pattern::new(&self.expand_pattern(pattern)?).matches(&subject) {
+                        matched = true;
+                        break;
+                    }
+                }
+                if !matched {
+                    continue;
+                }
+            }
+
+            status = self.run_body(&item.body)?;
+            if self.flow.is_some() {
+                break;
+            }
+            match item.terminator {
+                CaseTerminator::Break => break,
+                CaseTerminator::FallThrough => fall_through = true,
+                CaseTerminator::Continue => fall_through = false,
+            }
+        }
+        Ok(status)
+    }
+
+    /// Runs a function with `args` as its positional parameters and returns its status
+    pub fn call_function(
+        &mut self,
+        def: &FunctionDef,
+        args: Vec<String>,
+    ) -> Result<i32, ShellError> {
+        if self.locals.len() >= MAX_CALL_DEPTH {
+            return Err(ShellError::Nesting(def.name.clone(), MAX_CALL_DEPTH));
+        }
+
+        let positional = std::mem::replace(&mut self.positional, args);
+        // `break` and `continue` don't reach the loops around the call
+        let loops = std::mem::take(&mut self.loops);
+        self.locals.push(vec![]);
+        let traps = self.traps.take_inherited();
+
+        let r = self.run_redirected(&def.body, &def.redirects);
+
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        // a RETURN trap only runs for the function that set it
+        self.run_trap(Trap::Return);
+        self.traps.restore(traps);
+        for (name, var) in self.locals.pop().unwrap_or_default().into_iter().rev() {
+            self.vars.restore(&name, var);
+        }
+        self.loops = loops;
+        self.positional = positional;
+        r
+    }
+
+    /// Evaluates an arithmetic expression, or returns `None` if it is empty
+    fn eval_arith(&mut self, word: &Word) -> Result<Option<i64>, ShellError> {
+        let expr = self.expand_word(word)?;
+        if expr.trim().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(arith::eval(&expr, self)?))
+    }
+
+    /// Reports an error in the expression of `((...))` or `for ((...))`, which
+    /// only fails that command with status 1, while other errors are returned
+    fn arith_failed(&mut self, e: ShellError) -> Result<i32, ShellError> {
+        match e {
+            ShellError::Arith(..) => {
+                Command::new(String::new()).report(&e, self.location.as_deref());
+                Ok(1)
+            }
+            e => Err(e),
+        }
+    }
+
+    /// Performs the assignments in front of a command. When the command has words
+    /// the assignments only apply to it, so they are exported and the previous
+    /// values are returned to be restored once it ran.
+    fn assign(
+        &mut self,
+        assignments: &[Assignment],
+        temporary: bool,
+    ) -> Result<Vec<(String, Option<Var>)>, ShellError> {
+        let mut saved = vec![];
+        for a in assignments {
+            let r = self.expand_assignment(&a.value).and_then(|value| {
+                let old = self.vars.get_var(&a.name).cloned();
+                self.vars.set(&a.name, value)?;
+                Ok(old)
+            });
+            match r {
+                Ok(old) if temporary => {
+                    saved.push((a.name.clone(), old));
+                    self.vars.export(&a.name, true);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.restore(saved);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(saved)
+    }
+
+    fn restore(&mut self, saved: Vec<(String, Option<Var>)>) {
+        for (name, var) in saved.into_iter().rev() {
+            self.vars.restore(&name, var);
+        }
     }
 
     pub fn read_history<P: AsRef<Path>>(&mut self, path_buf: P) -> std::io::Result<()> {
@@ -96,3 +1083,51 @@ impl Shell {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `input` and returns the value it left in `x`
+    fn run(input: &str) -> String {
+        let mut shell = Shell::new();
+        shell.execute(input.to_string()).unwrap();
+        shell.vars.get("x").unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn source_nesting_is_limited() {
+        let mut shell = Shell::new();
+        shell.sources = MAX_SOURCE_DEPTH;
+        match shell.run_source("rc", b"x=1") {
+            Err(ShellError::SourceNesting(name, MAX_SOURCE_DEPTH)) => assert_eq!(name, "rc"),
+            r => panic!("expected a nesting error, got {r:?}"),
+        }
+        assert_eq!(shell.vars.get("x"), None);
+        shell.sources -= 1;
+        assert_eq!(shell.run_source("rc", b"x=1").unwrap(), 0);
+        assert_eq!(shell.vars.get("x"), Some("1"));
+        assert_eq!(shell.sources, MAX_SOURCE_DEPTH - 1);
+    }
+
+    #[test]
+    fn pipeline_stages_expand_in_their_own_copy() {
+        assert_eq!(run("true $((x = 5))"), "5");
+        assert_eq!(run("true $((x = 5)) | true"), "");
+        assert_eq!(run("true | true $((x = 5))"), "");
+    }
+
+    #[test]
+    fn arith_errors_fail_the_command() {
+        assert_eq!(run("((1/0)) 2>/dev/null; x=$?"), "1");
+        assert_eq!(
+            run("if ((1/0)) 2>/dev/null; then x=yes; else x=no; fi"),
+            "no"
+        );
+        assert_eq!(run("((1/0)) 2>/dev/null || x=failed"), "failed");
+        assert_eq!(
+            run("for ((i = 0; i < 1/0; i++)); do :; done 2>/dev/null; x=$?"),
+            "1"
+        );
+    }
+}
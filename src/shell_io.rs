@@ -1,12 +1,161 @@
-use std::fs::File;
-use std::io::{PipeReader, PipeWriter, Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{PipeReader, PipeWriter, Read, Seek, Write};
 use std::mem;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a file holding the given data, used for here-documents.
+/// The file is unlinked right away so it can be handed to child
+/// processes like any other redirection without blocking on a pipe.
+pub fn temp_file(data: &[u8]) -> std::io::Result<File> {
+    let n = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("shell-{}-{}", std::process::id(), n));
+
+    let mut f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let _ = std::fs::remove_file(&path);
+
+    f.write_all(data)?;
+    f.rewind()?;
+    Ok(f)
+}
+
+/// Duplicates a file descriptor the shell itself has open, e.g. one
+/// inherited from its parent, for redirections like `>&3`
+pub fn dup_raw_fd(fd: RawFd) -> std::io::Result<OwnedFd> {
+    // SAFETY: fcntl only inspects the descriptor, and it is only
+    // borrowed after fcntl confirmed that it is open
+    unsafe {
+        if libc::fcntl(fd, libc::F_GETFD) == -1 {
+            return Err(bad_fd());
+        }
+        BorrowedFd::borrow_raw(fd).try_clone_to_owned()
+    }
+}
+
+/// Reads the lines of the shell's stdin one byte at a time, so that nothing
+/// after the current line is consumed and the commands it runs can read the
+/// rest of the input themselves
+pub struct StdinLines;
+
+impl Iterator for StdinLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut line = vec![];
+        loop {
+            let mut byte = 0u8;
+            // SAFETY: read writes at most one byte into `byte`
+            match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+                1 if byte == b'\n' => break,
+                1 => line.push(byte),
+                0 if line.is_empty() => return None,
+                0 => break,
+                _ if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+                _ => return None,
+            }
+        }
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+}
+
+/// Places an output on the shell's own stdout, so that a forked copy
+/// of the shell writes everything, including its children, into it
+pub fn replace_stdout(output: &Output) -> std::io::Result<()> {
+    let fd = output.try_clone_fd()?;
+    // SAFETY: dup2 only operates on descriptors, both of which are open
+    if unsafe { libc::dup2(fd.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The shell's own descriptors, saved while the redirections of a command
+/// that runs inside the shell, like `((...)) > file`, are placed on them
+pub struct SavedFds {
+    saved: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl SavedFds {
+    /// Places each file on its descriptor, or closes the descriptor when `None`
+    pub fn replace(fds: Vec<(RawFd, Option<OwnedFd>)>) -> std::io::Result<SavedFds> {
+        // whatever is buffered belongs to the descriptors as they are now
+        std::io::stdout().flush()?;
+
+        let mut saved = SavedFds { saved: vec![] };
+        for (fd, file) in fds {
+            // SAFETY: fcntl, dup2 and close only operate on descriptors, and
+            // the copy is owned by nothing else
+            unsafe {
+                let copy = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10);
+                saved
+                    .saved
+                    .push((fd, (copy >= 0).then(|| OwnedFd::from_raw_fd(copy))));
+                match file {
+                    Some(f) if libc::dup2(f.as_raw_fd(), fd) < 0 => {
+                        let e = std::io::Error::last_os_error();
+                        saved.restore();
+                        return Err(e);
+                    }
+                    Some(_) => {}
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Puts the shell's descriptors back
+    pub fn restore(self) {
+        let _ = std::io::stdout().flush();
+        for (fd, copy) in self.saved.into_iter().rev() {
+            // SAFETY: as above, only descriptors are touched
+            unsafe {
+                match copy {
+                    Some(copy) => libc::dup2(copy.as_raw_fd(), fd),
+                    None => libc::close(fd),
+                };
+            }
+        }
+    }
+}
+
+fn bad_fd() -> std::io::Error {
+    std::io::Error::from_raw_os_error(libc::EBADF)
+}
+
+/// Stands in for a file descriptor closed with `<&-` or `>&-`
+struct ClosedFd;
+
+impl Read for ClosedFd {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(bad_fd())
+    }
+}
+
+impl Write for ClosedFd {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(bad_fd())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Err(bad_fd())
+    }
+}
 
 pub enum Input {
     File(File),
     Pipe(PipeReader),
     Stdin,
+    Closed,
     None,
 }
 
@@ -15,6 +164,24 @@ impl Input {
         mem::replace(self, Input::None)
     }
 
+    /// The descriptor of the pipe being read from, if it is one
+    pub fn pipe_fd(&self) -> Option<RawFd> {
+        match self {
+            Input::Pipe(p) => Some(p.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    /// Duplicates the underlying file descriptor so it can be placed on another one
+    pub fn try_clone_fd(&self) -> std::io::Result<OwnedFd> {
+        match self {
+            Input::File(f) => f.as_fd().try_clone_to_owned(),
+            Input::Pipe(p) => p.as_fd().try_clone_to_owned(),
+            Input::Stdin => std::io::stdin().as_fd().try_clone_to_owned(),
+            Input::Closed | Input::None => Err(bad_fd()),
+        }
+    }
+
     pub fn take_read(&mut self) -> Box<dyn Read> {
         let r = mem::replace(self, Input::None);
 
@@ -22,9 +189,7 @@ impl Input {
             Input::File(f) => Box::new(f),
             Input::Pipe(p) => Box::new(p),
             Input::Stdin => Box::new(std::io::stdin()),
-            Input::None => {
-                panic!("error: tried to convert none input to read")
-            }
+            Input::Closed | Input::None => Box::new(ClosedFd),
         }
     }
 }
@@ -35,9 +200,7 @@ impl From<Input> for Stdio {
             Input::File(f) => f.into(),
             Input::Pipe(p) => p.into(),
             Input::Stdin => Stdio::inherit(),
-            Input::None => {
-                panic!("error: tried to convert none input to stdio")
-            }
+            Input::Closed | Input::None => Stdio::null(),
         }
     }
 }
@@ -47,6 +210,7 @@ pub enum Output {
     Pipe(PipeWriter),
     Stdout,
     Stderr,
+    Closed,
     None,
 }
 
@@ -55,6 +219,36 @@ impl Output {
         mem::replace(self, Output::None)
     }
 
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(match self {
+            Output::File(f) => Output::File(f.try_clone()?),
+            Output::Pipe(p) => Output::Pipe(p.try_clone()?),
+            Output::Stdout => Output::Stdout,
+            Output::Stderr => Output::Stderr,
+            Output::Closed => Output::Closed,
+            Output::None => Output::None,
+        })
+    }
+
+    /// The descriptor of the pipe being written to, if it is one
+    pub fn pipe_fd(&self) -> Option<RawFd> {
+        match self {
+            Output::Pipe(p) => Some(p.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    /// Duplicates the underlying file descriptor so it can be placed on another one
+    pub fn try_clone_fd(&self) -> std::io::Result<OwnedFd> {
+        match self {
+            Output::File(f) => f.as_fd().try_clone_to_owned(),
+            Output::Pipe(p) => p.as_fd().try_clone_to_owned(),
+            Output::Stdout => std::io::stdout().as_fd().try_clone_to_owned(),
+            Output::Stderr => std::io::stderr().as_fd().try_clone_to_owned(),
+            Output::Closed | Output::None => Err(bad_fd()),
+        }
+    }
+
     pub fn take_write(&mut self) -> Box<dyn Write> {
         let r = mem::replace(self, Output::None);
 
@@ -63,9 +257,7 @@ impl Output {
             Output::Pipe(p) => Box::new(p),
             Output::Stdout => Box::new(std::io::stdout()),
             Output::Stderr => Box::new(std::io::stderr()),
-            Output::None => {
-                panic!("error: tried to convert none input to read")
-            }
+            Output::Closed | Output::None => Box::new(ClosedFd),
         }
     }
 }
@@ -75,11 +267,9 @@ impl From<Output> for Stdio {
         match value {
             Output::File(f) => f.into(),
             Output::Pipe(p) => p.into(),
-            Output::Stdout => Stdio::inherit(),
-            Output::Stderr => Stdio::inherit(),
-            Output::None => {
-                panic!("error: tried to convert none input to stdio")
-            }
+            Output::Stdout => std::io::stdout().into(),
+            Output::Stderr => std::io::stderr().into(),
+            Output::Closed | Output::None => Stdio::null(),
         }
     }
 }
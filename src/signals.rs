@@ -0,0 +1,165 @@
+/// This module contains the names and descriptions of the
+/// signals the shell reports and can send, and the handler
+/// that records the trapped ones as they arrive
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGNALS: &[(i32, &str, &str)] = &[
+    (libc::SIGHUP, "HUP", "Hangup"),
+    (libc::SIGINT, "INT", "Interrupt"),
+    (libc::SIGQUIT, "QUIT", "Quit"),
+    (libc::SIGILL, "ILL", "Illegal instruction"),
+    (libc::SIGTRAP, "TRAP", "Trace/breakpoint trap"),
+    (libc::SIGABRT, "ABRT", "Aborted"),
+    (libc::SIGBUS, "BUS", "Bus error"),
+    (libc::SIGFPE, "FPE", "Floating point exception"),
+    (libc::SIGKILL, "KILL", "Killed"),
+    (libc::SIGUSR1, "USR1", "User defined signal 1"),
+    (libc::SIGSEGV, "SEGV", "Segmentation fault"),
+    (libc::SIGUSR2, "USR2", "User defined signal 2"),
+    (libc::SIGPIPE, "PIPE", "Broken pipe"),
+    (libc::SIGALRM, "ALRM", "Alarm clock"),
+    (libc::SIGTERM, "TERM", "Terminated"),
+    (libc::SIGSTKFLT, "STKFLT", "Stack fault"),
+    (libc::SIGCHLD, "CHLD", "Child exited"),
+    (libc::SIGCONT, "CONT", "Continued"),
+    (libc::SIGSTOP, "STOP", "Stopped (signal)"),
+    (libc::SIGTSTP, "TSTP", "Stopped"),
+    (libc::SIGTTIN, "TTIN", "Stopped (tty input)"),
+    (libc::SIGTTOU, "TTOU", "Stopped (tty output)"),
+    (libc::SIGURG, "URG", "Urgent I/O condition"),
+    (libc::SIGXCPU, "XCPU", "CPU time limit exceeded"),
+    (libc::SIGXFSZ, "XFSZ", "File size limit exceeded"),
+    (libc::SIGVTALRM, "VTALRM", "Virtual timer expired"),
+    (libc::SIGPROF, "PROF", "Profiling timer expired"),
+    (libc::SIGWINCH, "WINCH", "Window changed"),
+    (libc::SIGIO, "IO", "I/O possible"),
+    (libc::SIGPWR, "PWR", "Power failure"),
+    (libc::SIGSYS, "SYS", "Bad system call"),
+];
+
+/// Returns the message printed when a process is killed by `signo`
+pub fn describe(signo: i32) -> String {
+    SIGNALS
+        .iter()
+        .find(|(n, _, _)| *n == signo)
+        .map(|(_, _, desc)| desc.to_string())
+        .unwrap_or_else(|| format!("Unknown signal {signo}"))
+}
+
+/// The numbers and names, without `SIG`, of the signals the shell knows, in order
+pub fn all() -> impl Iterator<Item = (i32, &'static str)> {
+    SIGNALS.iter().map(|(signo, name, _)| (*signo, *name))
+}
+
+/// Returns the name of a signal without `SIG`, like `INT`
+pub fn name(signo: i32) -> Option<&'static str> {
+    all().find(|(n, _)| *n == signo).map(|(_, name)| name)
+}
+
+/// Finds a signal by its number or its name, which may start
+/// with `SIG` and is matched regardless of case
+pub fn number(spec: &str) -> Option<i32> {
+    if let Ok(signo) = spec.parse::<i32>() {
+        return name(signo).map(|_| signo);
+    }
+    let spec = spec.to_ascii_uppercase();
+    let spec = spec.strip_prefix("SIG").unwrap_or(&spec);
+    all()
+        .find(|(_, name)| *name == spec)
+        .map(|(signo, _)| signo)
+}
+
+/// The signals that arrived since their traps last ran, by number
+static PENDING: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+extern "C" fn record(signo: libc::c_int) {
+    if let Some(pending) = PENDING.get(signo as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Installs `handler` for a signal, without restarting the system call it
+/// interrupts so that `wait` can return when a trapped signal arrives
+fn install(signo: i32, handler: libc::sighandler_t) {
+    // SAFETY: the handler either isn't a function or only stores to an atomic
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        libc::sigemptyset(&mut action.sa_mask);
+        // fails for SIGKILL and SIGSTOP, which can't be caught
+        libc::sigaction(signo, &action, std::ptr::null_mut());
+    }
+}
+
+/// Records a signal when it arrives instead of taking its usual action,
+/// so that its trap runs once the command being run is done
+pub fn catch(signo: i32) {
+    install(
+        signo,
+        record as extern "C" fn(libc::c_int) as libc::sighandler_t,
+    );
+}
+
+pub fn ignore(signo: i32) {
+    install(signo, libc::SIG_IGN);
+}
+
+/// Gives a signal back the action it has without a trap, which is to be
+/// ignored for the signals of `JOB_SIGNALS` in a shell with a terminal
+pub fn reset(signo: i32, terminal: bool) {
+    match terminal && JOB_SIGNALS.contains(&signo) {
+        true => ignore(signo),
+        false => install(signo, libc::SIG_DFL),
+    }
+}
+
+/// Blocks every signal in the calling thread, so that the signals sent to the
+/// shell are handled by the thread running it, interrupting what it waits for
+pub fn block_all() {
+    // SAFETY: these calls only change the signal mask of this thread
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigfillset(&mut set);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+}
+
+/// The first signal that arrived since the traps last ran, without taking it
+pub fn pending() -> Option<i32> {
+    (1..PENDING.len() as i32).find(|&signo| PENDING[signo as usize].load(Ordering::SeqCst))
+}
+
+/// Takes the signals that arrived since the traps last ran, in order
+pub fn take_pending() -> Vec<i32> {
+    (1..PENDING.len() as i32)
+        .filter(|&signo| PENDING[signo as usize].swap(false, Ordering::SeqCst))
+        .collect()
+}
+
+/// The signals from the keyboard and terminal that an interactive shell
+/// ignores, so that only the job in the foreground gets them
+const JOB_SIGNALS: &[i32] = &[
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// Ignores the signals of `JOB_SIGNALS` in the shell
+pub fn ignore_job_signals() {
+    for &signo in JOB_SIGNALS {
+        // SAFETY: ignoring a signal installs no handler
+        unsafe { libc::signal(signo, libc::SIG_IGN) };
+    }
+}
+
+/// Restores the default action of the signals of `JOB_SIGNALS` in a job,
+/// since ignored signals stay ignored across exec. Only calls
+/// async-signal-safe functions, so it can run between fork and exec.
+pub fn default_job_signals() {
+    for &signo in JOB_SIGNALS {
+        // SAFETY: restoring the default action installs no handler
+        unsafe { libc::signal(signo, libc::SIG_DFL) };
+    }
+}
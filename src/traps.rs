@@ -0,0 +1,120 @@
+/// This module contains the traps set with `trap`, the commands the
+/// shell runs when it gets a signal or reaches one of the conditions
+/// with a name of their own, like exiting
+use crate::signals;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What a trap is set on, in the order `trap -p` lists them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    /// The shell exiting, also called 0
+    Exit,
+    Signal(i32),
+    /// A simple command being about to run
+    Debug,
+    /// A command failing
+    Err,
+    /// A function or sourced file returning
+    Return,
+}
+
+impl Trap {
+    /// Parses the name or number of a signal, or the name of a condition
+    pub fn parse(spec: &str) -> Option<Trap> {
+        match spec.to_ascii_uppercase().as_str() {
+            "0" | "EXIT" => Some(Trap::Exit),
+            "DEBUG" => Some(Trap::Debug),
+            "ERR" => Some(Trap::Err),
+            "RETURN" => Some(Trap::Return),
+            _ => signals::number(spec).map(Trap::Signal),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::Exit => write!(f, "EXIT"),
+            Trap::Signal(signo) => match signals::name(*signo) {
+                Some(name) => write!(f, "SIG{name}"),
+                None => write!(f, "{signo}"),
+            },
+            Trap::Debug => write!(f, "DEBUG"),
+            Trap::Err => write!(f, "ERR"),
+            Trap::Return => write!(f, "RETURN"),
+        }
+    }
+}
+
+/// The traps that are set, with an empty command for the ignored ones
+#[derive(Default)]
+pub struct Traps {
+    traps: BTreeMap<Trap, String>,
+}
+
+impl Traps {
+    /// The command of a trap, which is empty if it is ignored
+    pub fn get(&self, trap: Trap) -> Option<&str> {
+        self.traps.get(&trap).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Trap, &str)> {
+        self.traps
+            .iter()
+            .map(|(trap, command)| (*trap, command.as_str()))
+    }
+
+    /// Sets a trap to run `command`, or to ignore the signal if it is empty
+    pub fn set(&mut self, trap: Trap, command: String) {
+        if let Trap::Signal(signo) = trap {
+            match command.is_empty() {
+                true => signals::ignore(signo),
+                false => signals::catch(signo),
+            }
+        }
+        self.traps.insert(trap, command);
+    }
+
+    /// Removes a trap, giving its signal the action of a shell with
+    /// or without a `terminal` back
+    pub fn reset(&mut self, trap: Trap, terminal: bool) {
+        if let (Some(_), Trap::Signal(signo)) = (self.traps.remove(&trap), trap) {
+            signals::reset(signo, terminal);
+        }
+    }
+
+    /// Removes a trap without changing the action of its signal
+    pub fn take(&mut self, trap: Trap) -> Option<String> {
+        self.traps.remove(&trap)
+    }
+
+    /// Removes the DEBUG, ERR and RETURN traps, which functions don't inherit
+    pub fn take_inherited(&mut self) -> Vec<(Trap, String)> {
+        [Trap::Debug, Trap::Err, Trap::Return]
+            .into_iter()
+            .filter_map(|trap| Some((trap, self.take(trap)?)))
+            .collect()
+    }
+
+    /// Puts back the traps `take_inherited` removed, except for those the function set itself
+    pub fn restore(&mut self, traps: Vec<(Trap, String)>) {
+        for (trap, command) in traps {
+            self.traps.entry(trap).or_insert(command);
+        }
+    }
+
+    /// Removes the traps that run commands in a forked copy of the shell,
+    /// as it only keeps ignoring the signals that are ignored
+    pub fn forget_commands(&mut self, terminal: bool) {
+        self.traps.retain(|trap, command| {
+            if command.is_empty() {
+                return true;
+            }
+            if let Trap::Signal(signo) = trap {
+                signals::reset(*signo, terminal);
+            }
+            false
+        });
+    }
+}
@@ -0,0 +1,117 @@
+/// This module contains the store for shell variables,
+/// keeping track of which of them are exported to child
+/// processes and which are read-only
+use crate::error::ShellError;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct Var {
+    /// `None` for variables that were declared, e.g. with `export NAME`, but never set
+    pub value: Option<String>,
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    vars: HashMap<String, Var>,
+}
+
+impl Variables {
+    /// Creates a store holding the process environment, all exported
+    pub fn from_env() -> Self {
+        let vars = std::env::vars()
+            .map(|(name, value)| {
+                let var = Var {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, var)
+            })
+            .collect();
+        Variables { vars }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).and_then(|v| v.value.as_deref())
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Var> {
+        self.vars.get(name)
+    }
+
+    /// Puts back a variable saved with `get_var`, ignoring whether it is read-only
+    pub fn restore(&mut self, name: &str, var: Option<Var>) {
+        match var {
+            Some(var) => self.vars.insert(name.to_string(), var),
+            None => self.vars.remove(name),
+        };
+    }
+
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), ShellError> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(ShellError::Readonly(name.to_string()));
+        }
+        var.value = Some(value);
+        Ok(())
+    }
+
+    pub fn export(&mut self, name: &str, exported: bool) {
+        self.vars.entry(name.to_string()).or_default().exported = exported;
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), ShellError> {
+        if self.vars.get(name).is_some_and(|v| v.readonly) {
+            return Err(ShellError::Readonly(name.to_string()));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    /// All variables sorted by name
+    pub fn iter(&self) -> Vec<(&str, &Var)> {
+        let mut vars = self
+            .vars
+            .iter()
+            .map(|(name, var)| (name.as_str(), var))
+            .collect::<Vec<_>>();
+        vars.sort_by_key(|(name, _)| *name);
+        vars
+    }
+
+    /// The environment handed to child processes
+    pub fn exported(&self) -> Vec<(&str, &str)> {
+        self.iter()
+            .into_iter()
+            .filter(|(_, var)| var.exported)
+            .filter_map(|(name, var)| Some((name, var.value.as_deref()?)))
+            .collect()
+    }
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes a value so that it can be read back by the shell,
+/// used when listing variables
+pub fn quote(s: &str) -> String {
+    let safe = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c));
+    if safe {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}